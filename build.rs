@@ -0,0 +1,5 @@
+fn main() {
+    // Static files are embedded with include_dir, which only notices changes to the files it
+    // already embeds; this also catches files being added or removed.
+    println!("cargo:rerun-if-changed=static");
+}
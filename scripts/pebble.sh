@@ -0,0 +1,20 @@
+#!/usr/bin/env sh
+# Runs the ACME test against Pebble, Let's Encrypt's test server: orders a certificate for
+# localhost, then renews it. Needs docker, and port 5002 free for the HTTP-01 challenges.
+
+set -eu
+
+cd "$(dirname "$0")/.."
+
+ca_root="$(mktemp)"
+container="$(docker run --rm -d --network host ghcr.io/letsencrypt/pebble)"
+trap 'docker stop "$container" >/dev/null; rm -f "$ca_root"' EXIT
+
+curl -fsSL https://raw.githubusercontent.com/letsencrypt/pebble/main/test/certs/pebble.minica.pem -o "$ca_root"
+
+# Pebble takes a moment to listen.
+until curl -fsS --cacert "$ca_root" https://localhost:14000/dir >/dev/null 2>&1; do
+  sleep 1
+done
+
+PEBBLE_CA_ROOT="$ca_root" cargo test -- --ignored orders_and_renews_certificates_from_pebble
@@ -0,0 +1,28 @@
+#!/usr/bin/env sh
+# Downloads the third-party scripts served from static/vendor and checks them against the
+# hashes published upstream. Run it after changing a version below, then commit the files.
+
+set -eu
+
+cd "$(dirname "$0")/../static"
+mkdir -p vendor
+
+fetch() {
+  url="$1"
+  file="vendor/$2"
+  sha384="$3"
+
+  curl -fsSL "$url" -o "$file.tmp"
+  actual="$(openssl dgst -sha384 -binary "$file.tmp" | openssl base64 -A)"
+  if [ "$actual" != "$sha384" ]; then
+    rm "$file.tmp"
+    echo "$url: expected sha384-$sha384, got sha384-$actual" >&2
+    exit 1
+  fi
+
+  mv "$file.tmp" "$file"
+  echo "$file: sha384-$actual"
+}
+
+fetch https://unpkg.com/htmx.org@2.0.2/dist/htmx.min.js htmx.min.js \
+  Y7hw+L/jvKeWIRRkqWYfPcvVxHzVzn5REgzbawhxAuQGwX1XWe70vji+VSeHOThJ
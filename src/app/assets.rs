@@ -0,0 +1,211 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::OnceLock;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use include_dir::{include_dir, Dir};
+use sha2::{Digest, Sha384};
+use xxhash_rust::xxh3::xxh3_64;
+use crate::app::controller::encoding::{Effort, Encoded, Encoding};
+
+/// Where static files are served from.
+pub const STATIC_PREFIX: &str = "/static";
+
+/// Extensions of precompressed siblings, served by `ServeDir` in place of the original file.
+const PRECOMPRESSED_EXTENSIONS: [&str; 2] = ["br", "gz"];
+
+/// The `static/` directory as it was at build time, served unless `server.static_dir` is set.
+static EMBEDDED: Dir<'static> = include_dir!("$CARGO_MANIFEST_DIR/static");
+
+static MANIFEST: OnceLock<Manifest> = OnceLock::new();
+
+thread_local! {
+    /// Names passed to [`url`] and [`integrity`] while [`recording_references`] runs.
+    static REFERENCED: RefCell<Option<BTreeSet<String>>> = const { RefCell::new(None) };
+}
+
+/// The embedded static files, compressed in every encoding, and their content-hashed names, e.g.
+/// `styles.css` is served as `styles.1f2e3d4c5b6a7988.css` too. Such URLs change whenever the
+/// file does, so they can be cached forever.
+#[derive(Debug, Default)]
+pub struct Manifest {
+    /// Path relative to the static directory, to its fingerprinted version.
+    fingerprinted: HashMap<String, String>,
+    /// The reverse.
+    original: HashMap<String, String>,
+    /// Path relative to the static directory, to its contents.
+    files: HashMap<String, StaticFile>,
+}
+
+#[derive(Debug)]
+struct StaticFile {
+    content_type: HeaderValue,
+    variants: Vec<Encoded>,
+    /// Subresource Integrity metadata, `sha384-<base64>`.
+    integrity: String,
+}
+
+impl Manifest {
+    /// Hashes and compresses every embedded file. Precompressed siblings are skipped: the
+    /// files are compressed as hard as they would be ahead of time.
+    pub fn build() -> Manifest {
+        let mut manifest = Manifest::default();
+
+        let mut pending = vec![&EMBEDDED];
+        while let Some(dir) = pending.pop() {
+            pending.extend(dir.dirs());
+
+            for file in dir.files() {
+                let path = file.path();
+                let is_precompressed = path.extension()
+                    .is_some_and(|extension| PRECOMPRESSED_EXTENSIONS.iter().any(|p| extension == *p));
+                if let Some(name) = path.to_str().filter(|_| !is_precompressed) {
+                    manifest.insert(name, file.contents());
+                }
+            }
+        }
+
+        manifest
+    }
+
+    fn insert(&mut self, name: &str, contents: &[u8]) {
+        let fingerprinted = fingerprint(name, xxh3_64(contents));
+
+        let content_type = mime_guess::from_path(name).first_or_octet_stream();
+        let file = StaticFile {
+            content_type: HeaderValue::from_str(content_type.as_ref())
+                .unwrap_or(HeaderValue::from_static("application/octet-stream")),
+            variants: Encoding::ALL
+                .into_iter()
+                .map(|encoding| Encoded::new(encoding, contents, Effort::Best))
+                .collect(),
+            integrity: format!("sha384-{}", STANDARD.encode(Sha384::digest(contents))),
+        };
+
+        self.original.insert(fingerprinted.clone(), name.to_string());
+        self.fingerprinted.insert(name.to_string(), fingerprinted);
+        self.files.insert(name.to_string(), file);
+    }
+
+    /// Makes this manifest the one [`url`] uses. Only the first call has an effect.
+    pub fn install(self) {
+        let _ = MANIFEST.set(self);
+    }
+}
+
+/// `styles.css` with hash `h` becomes `styles.<h>.css`.
+fn fingerprint(name: &str, hash: u64) -> String {
+    let (directory, file) = name.rsplit_once('/').map_or(("", name), |(d, f)| (d, f));
+    let file = match file.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!("{}.{:016x}.{}", stem, hash, extension),
+        _ => format!("{}.{:016x}", file, hash),
+    };
+
+    match directory {
+        "" => file,
+        directory => format!("{}/{}", directory, file),
+    }
+}
+
+/// URL of a static file for templates, e.g. `{{ crate::app::assets::url("styles.css") }}`.
+/// Fingerprinted when serving embedded files, plain when serving from `server.static_dir`.
+pub fn url(name: &str) -> String {
+    record_reference(name);
+
+    let name = MANIFEST.get()
+        .and_then(|manifest| manifest.fingerprinted.get(name))
+        .map_or(name, String::as_str);
+
+    format!("{}/{}", STATIC_PREFIX, name)
+}
+
+/// Subresource Integrity of a static file for templates, e.g.
+/// `integrity="{{ crate::app::assets::integrity("vendor/htmx.min.js") }}"`, so that browsers
+/// refuse a file altered after the build. Empty when serving from `server.static_dir`, where
+/// files may change at any time, which disables the check.
+pub fn integrity(name: &str) -> String {
+    record_reference(name);
+
+    MANIFEST.get()
+        .and_then(|manifest| manifest.files.get(name))
+        .map(|file| file.integrity.clone())
+        .unwrap_or_default()
+}
+
+/// Runs `render` and returns the names of the static files it referenced, to check that they
+/// exist.
+pub fn recording_references<T>(render: impl FnOnce() -> T) -> (T, BTreeSet<String>) {
+    REFERENCED.with_borrow_mut(|referenced| *referenced = Some(BTreeSet::new()));
+    let rendered = render();
+    let referenced = REFERENCED.with_borrow_mut(Option::take).unwrap_or_default();
+    (rendered, referenced)
+}
+
+fn record_reference(name: &str) {
+    REFERENCED.with_borrow_mut(|referenced| {
+        if let Some(referenced) = referenced {
+            referenced.insert(name.to_string());
+        }
+    });
+}
+
+/// Whether a static file is served, from `static_dir` when set or embedded otherwise.
+pub fn exists(static_dir: Option<&Path>, name: &str) -> bool {
+    match static_dir {
+        Some(static_dir) => static_dir.join(name).is_file(),
+        None => EMBEDDED.get_file(name).is_some(),
+    }
+}
+
+/// Serves fingerprinted URLs from the original files, with a policy to cache them forever.
+/// Runs inside the `/static` nest, so paths come without the prefix.
+pub async fn serve_fingerprinted(State(immutable): State<HeaderValue>, mut request: Request, next: Next) -> Response {
+    let original = MANIFEST.get()
+        .and_then(|manifest| manifest.original.get(request.uri().path().trim_start_matches('/')));
+
+    let Some(original) = original else {
+        return next.run(request).await;
+    };
+
+    let path_and_query = match request.uri().query() {
+        Some(query) => format!("/{}?{}", original, query),
+        None => format!("/{}", original),
+    };
+    let mut parts = request.uri().clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *request.uri_mut() = uri;
+    }
+
+    let mut response = next.run(request).await;
+    if response.status().is_success() {
+        response.headers_mut().insert(header::CACHE_CONTROL, immutable);
+    }
+    response
+}
+
+/// Serves an embedded file in the smallest encoding the client accepts, with its ETag. Runs
+/// inside the `/static` nest, so paths come without the prefix.
+pub async fn serve_embedded(method: Method, uri: Uri, headers: HeaderMap) -> Response {
+    if method != Method::GET && method != Method::HEAD {
+        return StatusCode::METHOD_NOT_ALLOWED.into_response();
+    }
+
+    let file = MANIFEST.get()
+        .and_then(|manifest| manifest.files.get(uri.path().trim_start_matches('/')));
+    let Some(file) = file else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let variant = Encoded::smallest_accepted(&file.variants, &headers);
+    let mut response_headers = variant.headers();
+    response_headers.insert(header::CONTENT_TYPE, file.content_type.clone());
+
+    (response_headers, Body::from(variant.body())).into_response()
+}
@@ -0,0 +1,949 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use http::{HeaderValue, Method, Uri};
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use tracing_subscriber::EnvFilter;
+use crate::app::error::StartupError;
+
+const DEFAULT_CONFIG_FILE: &str = "mywebsite.toml";
+const ENV_PREFIX: &str = "MYWEBSITE__";
+const ENV_SEPARATOR: &str = "__";
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub storage: StorageConfig,
+    pub features: FeaturesConfig,
+    pub cache: CacheConfig,
+    pub logging: LoggingConfig,
+    pub telemetry: TelemetryConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Public listeners, a single address or a list, e.g. `["0.0.0.0:80", "[::]:80"]`.
+    #[serde(rename = "listen_address", deserialize_with = "one_or_many")]
+    pub listen_addresses: Vec<ListenAddress>,
+    /// Private listener serving operations endpoints such as `/healthz`, which are not routed
+    /// on the public listeners. Typically a loopback address.
+    pub ops_listen_address: Option<ListenAddress>,
+    /// Permissions of Unix domain sockets created by the server, e.g. `0o660`.
+    pub unix_socket_mode: Option<u32>,
+    /// Serve static files from this directory, re-read on every request, instead of the copy
+    /// embedded at build time. Meant for editing them while the server runs.
+    pub static_dir: Option<PathBuf>,
+    pub body_limit: usize,
+    /// How long in-flight requests may take to complete once a shutdown signal is received.
+    pub shutdown_timeout_secs: u64,
+    /// Serve HTTPS on the public listeners when set.
+    pub tls: Option<TlsConfig>,
+    /// Security headers added to the responses of the public listeners.
+    pub security: SecurityConfig,
+    /// Limits on how often a client may call some routes.
+    pub rate_limit: RateLimitConfig,
+    /// Bounds on the work the public listeners take on.
+    pub limits: LimitsConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first.
+    pub cert_path: PathBuf,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1).
+    pub key_path: PathBuf,
+    /// How often the certificate and key files are checked for changes.
+    #[serde(default = "default_tls_reload_interval_secs")]
+    pub reload_interval_secs: u64,
+    /// Plain HTTP listener redirecting every request to HTTPS.
+    #[serde(default)]
+    pub redirect_listen_address: Option<ListenAddress>,
+    /// Obtain and renew the certificate automatically, writing it to `cert_path` and `key_path`.
+    #[serde(default)]
+    pub acme: Option<AcmeConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AcmeConfig {
+    /// Names the certificate is issued for. Each must resolve to this server, which answers
+    /// HTTP-01 challenges on `redirect_listen_address`.
+    pub domains: Vec<String>,
+    /// Contact URLs for the account, e.g. `mailto:admin@example.com`.
+    #[serde(default)]
+    pub contact: Vec<String>,
+    #[serde(default = "default_acme_directory_url")]
+    pub directory_url: String,
+    /// PEM root certificate of the ACME server's HTTPS endpoint, for test servers like Pebble.
+    #[serde(default)]
+    pub ca_root: Option<PathBuf>,
+    /// Where the account key is kept.
+    #[serde(default = "default_acme_state_dir")]
+    pub state_dir: PathBuf,
+    #[serde(default = "default_acme_renew_before_days")]
+    pub renew_before_days: u64,
+    #[serde(default = "default_acme_check_interval_secs")]
+    pub check_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityConfig {
+    /// Strict-Transport-Security max-age, only sent when serving HTTPS; 0 leaves it out.
+    pub hsts_max_age_secs: u64,
+    /// Extend Strict-Transport-Security to every subdomain, which must then all serve HTTPS.
+    pub hsts_include_subdomains: bool,
+    /// Referrer-Policy; empty leaves it out.
+    pub referrer_policy: String,
+    /// Permissions-Policy; empty leaves it out.
+    pub permissions_policy: String,
+    /// Sources allowed to embed the pages in a frame, sent as the CSP `frame-ancestors`
+    /// directive, which is enforced even when `csp_report_only` is set.
+    pub frame_ancestors: Vec<String>,
+    /// Content-Security-Policy directives. `{nonce}` is replaced with the nonce of the request,
+    /// which templates get from `crate::app::security::nonce()`. Violations are reported to
+    /// `/csp-report`. Empty leaves only `frame-ancestors`.
+    pub content_security_policy: String,
+    /// Send the policy as Content-Security-Policy-Report-Only, to try it out without blocking
+    /// anything.
+    pub csp_report_only: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Clients tracked per route and key; beyond that, the least recently seen are forgotten.
+    pub max_clients: usize,
+    /// Take the client address from the last `X-Forwarded-For` entry, as set by a reverse proxy.
+    /// Otherwise the peer address is used, and clients on Unix domain sockets share one limit.
+    pub trust_forwarded_for: bool,
+    /// Limits by route, e.g. `"POST /contact"`. Replaces the default routes rather than adding to
+    /// them.
+    pub routes: BTreeMap<String, RouteRateLimit>,
+}
+
+/// Requests over these limits are answered with the 503 page, telling clients to come back after
+/// `retry_after_secs`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// How long a request may take until its response starts.
+    pub request_timeout_ms: u64,
+    /// Timeouts of specific routes, e.g. `"POST /contact"`, instead of `request_timeout_ms`.
+    /// Replaces the default routes rather than adding to them.
+    pub route_timeouts_ms: BTreeMap<String, u64>,
+    /// Requests handled at once; more are turned away right away rather than queued.
+    pub max_in_flight_requests: usize,
+    /// How long an HTTP/1 client may take to send the headers of a request.
+    pub header_read_timeout_ms: u64,
+    /// Connections open at once on the public listeners; more wait in the listen backlog until
+    /// one closes.
+    pub max_connections: usize,
+    pub retry_after_secs: u64,
+}
+
+/// A token bucket: `burst` requests at once, then `per_minute` on average.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteRateLimit {
+    pub burst: u32,
+    pub per_minute: u32,
+    /// Also limit by the value of this form field, e.g. `email`, whatever the client address.
+    #[serde(default)]
+    pub form_field: Option<String>,
+}
+
+fn default_log_file_prefix() -> String {
+    "mywebsite.log".to_string()
+}
+
+fn default_log_rotation() -> LogRotation {
+    LogRotation::Daily
+}
+
+fn default_log_max_files() -> usize {
+    7
+}
+
+fn default_tls_reload_interval_secs() -> u64 {
+    60
+}
+
+fn default_acme_directory_url() -> String {
+    "https://acme-v02.api.letsencrypt.org/directory".to_string()
+}
+
+fn default_acme_state_dir() -> PathBuf {
+    PathBuf::from("acme")
+}
+
+fn default_acme_renew_before_days() -> u64 {
+    30
+}
+
+fn default_acme_check_interval_secs() -> u64 {
+    12 * 60 * 60
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub path: PathBuf,
+    pub max_connections: u32,
+    /// What `serve` does with the embedded schema migrations at startup.
+    pub migrations: MigrationMode,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationMode {
+    /// Create the database if needed and apply pending migrations.
+    Apply,
+    /// Refuse to start unless every migration is applied; never writes to the database.
+    Verify,
+    /// Trust whatever schema the database has.
+    Off,
+}
+
+/// Toggles that take effect on SIGHUP, without a restart.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeaturesConfig {
+    /// Accept messages on POST /contact.
+    pub contact_form: bool,
+    /// List received messages on /messages.
+    pub messages_page: bool,
+}
+
+/// In-process cache of rendered /messages pages, emptied whenever a message is created.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// How long a page is served from the cache; 0 disables it.
+    pub ttl_ms: u64,
+    /// Pages kept at most, one per page, page size and content encoding.
+    pub max_entries: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// File used by the `json` backend, one message per line.
+    pub json_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Sqlite,
+    Json,
+    Memory,
+}
+
+/// What `serve` logs, and where.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// Levels per module, in the syntax of `RUST_LOG`, e.g. `info,mywebsite=debug,sqlx=warn`.
+    /// Reloaded on SIGHUP.
+    pub level: String,
+    /// Write to rotated files instead of stdout.
+    pub file: Option<LogFileConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable, spread over several lines.
+    Pretty,
+    /// One JSON object per line, with the fields of the enclosing spans.
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogFileConfig {
+    pub directory: PathBuf,
+    /// Files are named after it, followed by the date unless `rotation` is `never`.
+    #[serde(default = "default_log_file_prefix")]
+    pub prefix: String,
+    #[serde(default = "default_log_rotation")]
+    pub rotation: LogRotation,
+    /// Rotated files kept; older ones are deleted.
+    #[serde(default = "default_log_max_files")]
+    pub max_files: usize,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    Never,
+}
+
+/// Export of request traces to an OpenTelemetry collector, over OTLP/HTTP.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// Traces endpoint of the collector, e.g. `http://localhost:4318/v1/traces`. Nothing is
+    /// exported when unset.
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+    /// Share of traces exported, for requests that do not come with a sampling decision in
+    /// their `traceparent` header.
+    pub sample_ratio: f64,
+}
+
+/// Where a listener accepts connections:
+/// - `127.0.0.1:3000` or `[::1]:3000` for TCP,
+/// - `unix:/run/mywebsite.sock` for a Unix domain socket,
+/// - `systemd` or `systemd:<name>` for a socket passed by systemd socket activation, the first
+///   one or the one with the given `FileDescriptorName=`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    Systemd(Option<String>),
+}
+
+impl FromStr for ListenAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("missing socket path after unix:".to_string());
+            }
+            return Ok(ListenAddress::Unix(PathBuf::from(path)));
+        }
+
+        if s == "systemd" {
+            return Ok(ListenAddress::Systemd(None));
+        }
+
+        if let Some(name) = s.strip_prefix("systemd:") {
+            return Ok(ListenAddress::Systemd(Some(name.to_string())));
+        }
+
+        SocketAddr::from_str(s)
+            .map(ListenAddress::Tcp)
+            .map_err(|e| format!("{} (expected host:port, unix:<path> or systemd[:<name>])", e))
+    }
+}
+
+impl TryFrom<String> for ListenAddress {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ListenAddress> for String {
+    fn from(address: ListenAddress) -> Self {
+        address.to_string()
+    }
+}
+
+impl Display for ListenAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddress::Tcp(address) => write!(f, "{}", address),
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+            ListenAddress::Systemd(None) => write!(f, "systemd"),
+            ListenAddress::Systemd(Some(name)) => write!(f, "systemd:{}", name),
+        }
+    }
+}
+
+/// Accepts either a single value or a list, so a lone address needs no brackets.
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where D: Deserializer<'de>, T: Deserialize<'de> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => Ok(vec![value]),
+        OneOrMany::Many(values) => Ok(values),
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            listen_addresses: vec![ListenAddress::Tcp(SocketAddr::from(([127, 0, 0, 1], 3000)))],
+            ops_listen_address: None,
+            unix_socket_mode: None,
+            static_dir: None,
+            body_limit: 1024 * 1024,
+            shutdown_timeout_secs: 30,
+            tls: None,
+            security: SecurityConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            limits: LimitsConfig::default(),
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            request_timeout_ms: 10_000,
+            route_timeouts_ms: BTreeMap::from([("POST /contact".to_string(), 5_000)]),
+            max_in_flight_requests: 256,
+            header_read_timeout_ms: 10_000,
+            max_connections: 1024,
+            retry_after_secs: 5,
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        let contact = RouteRateLimit {
+            burst: 3,
+            per_minute: 1,
+            form_field: Some("email".to_string()),
+        };
+        // A page breaking its policy may send a report for each blocked resource on every load.
+        let csp_report = RouteRateLimit {
+            burst: 20,
+            per_minute: 20,
+            form_field: None,
+        };
+
+        RateLimitConfig {
+            max_clients: 10_000,
+            trust_forwarded_for: false,
+            routes: BTreeMap::from([
+                ("POST /contact".to_string(), contact),
+                ("POST /csp-report".to_string(), csp_report),
+            ]),
+        }
+    }
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        SecurityConfig {
+            hsts_max_age_secs: 365 * 24 * 60 * 60,
+            hsts_include_subdomains: false,
+            referrer_policy: "strict-origin-when-cross-origin".to_string(),
+            permissions_policy: "camera=(), microphone=(), geolocation=()".to_string(),
+            frame_ancestors: vec!["'none'".to_string()],
+            content_security_policy: "default-src 'self'; script-src 'self' 'nonce-{nonce}'; \
+                style-src 'self' 'nonce-{nonce}'; img-src 'self'; object-src 'none'; base-uri 'none'; \
+                form-action 'self'".to_string(),
+            csp_report_only: false,
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            path: PathBuf::from("db/database.sqlite"),
+            max_connections: 5,
+            migrations: MigrationMode::Apply,
+        }
+    }
+}
+
+impl DatabaseConfig {
+    pub fn connect_options(&self) -> SqliteConnectOptions {
+        SqliteConnectOptions::new().filename(&self.path)
+    }
+
+    pub fn pool_options(&self) -> SqlitePoolOptions {
+        SqlitePoolOptions::new().max_connections(self.max_connections)
+    }
+}
+
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        FeaturesConfig {
+            contact_form: true,
+            messages_page: true,
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            ttl_ms: 5000,
+            max_entries: 256,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            format: LogFormat::Pretty,
+            level: "info".to_string(),
+            file: None,
+        }
+    }
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            otlp_endpoint: None,
+            service_name: "mywebsite".to_string(),
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: StorageBackend::Sqlite,
+            json_path: PathBuf::from("db/messages.jsonl"),
+        }
+    }
+}
+
+/// Where a configuration layer comes from, from lowest to highest precedence.
+#[derive(Debug, Default)]
+pub struct Sources {
+    /// Explicit configuration file. When absent, `mywebsite.toml` is used if it exists.
+    pub file: Option<PathBuf>,
+    /// Environment variables, e.g. `MYWEBSITE__SERVER__LISTEN_ADDRESS=0.0.0.0:80`. Only those
+    /// starting with `MYWEBSITE__` are used, and they must be valid UTF-8.
+    pub env: Vec<(OsString, OsString)>,
+    /// `key=value` overrides coming from the command line, e.g. `server.body_limit=4096`.
+    pub overrides: Vec<String>,
+}
+
+impl Config {
+    /// Builds the configuration by layering defaults, the configuration file, the environment
+    /// and the command line, then validates the result.
+    pub fn load(sources: &Sources) -> Result<Config, StartupError> {
+        let mut layered = toml::Value::try_from(Config::default())
+            .expect("default configuration is serializable");
+
+        if let Some(file) = read_file(sources.file.as_deref())? {
+            merge(&mut layered, file, "");
+        }
+
+        for (name, value) in &sources.env {
+            if !name.as_encoded_bytes().starts_with(ENV_PREFIX.as_bytes()) {
+                continue;
+            }
+
+            let (Some(name), Some(value)) = (name.to_str(), value.to_str()) else {
+                return Err(StartupError::InvalidSetting(name.to_string_lossy().into_owned(), "is not valid UTF-8".to_string()));
+            };
+            let key = name[ENV_PREFIX.len()..].to_lowercase().replace(ENV_SEPARATOR, ".");
+            set(&mut layered, &key, parse_value(value))
+                .map_err(|reason| StartupError::InvalidSetting(key.clone(), reason))?;
+        }
+
+        for over in &sources.overrides {
+            let (key, value) = over.split_once('=')
+                .ok_or_else(|| StartupError::InvalidOverride(over.clone()))?;
+            let key = key.trim();
+            set(&mut layered, key, parse_value(value.trim()))
+                .map_err(|reason| StartupError::InvalidSetting(key.to_string(), reason))?;
+        }
+
+        let config: Config = serde_path_to_error::deserialize(layered)
+            .map_err(|e| StartupError::InvalidSetting(e.path().to_string(), e.inner().message().to_string()))?;
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// This configuration with the settings that can change at runtime taken from `loaded`.
+    /// Everything else, such as listeners or storage, only changes on restart.
+    pub fn reloaded(&self, loaded: &Config) -> Config {
+        let mut reloaded = self.clone();
+        reloaded.features = loaded.features.clone();
+        reloaded.server.shutdown_timeout_secs = loaded.server.shutdown_timeout_secs;
+        reloaded.server.rate_limit = loaded.server.rate_limit.clone();
+        reloaded.logging.level = loaded.logging.level.clone();
+        reloaded
+    }
+
+    fn validate(&self) -> Result<(), StartupError> {
+        if self.server.body_limit == 0 {
+            return Err(invalid("server.body_limit", "must be greater than 0"));
+        }
+
+        if self.server.listen_addresses.is_empty() {
+            return Err(invalid("server.listen_address", "must list at least one address"));
+        }
+
+        let mut bound = Vec::new();
+        let listeners = self.server.listen_addresses.iter()
+            .map(|address| ("server.listen_address", address))
+            .chain(self.server.ops_listen_address.iter().map(|address| ("server.ops_listen_address", address)))
+            .chain(self.server.tls.iter().flat_map(|tls| &tls.redirect_listen_address).map(|address| ("server.tls.redirect_listen_address", address)));
+        for (key, address) in listeners {
+            if bound.contains(&address) {
+                return Err(invalid(key, &format!("{} is already used by another listener", address)));
+            }
+            bound.push(address);
+        }
+
+        if self.server.unix_socket_mode.is_some_and(|mode| mode > 0o777) {
+            return Err(invalid("server.unix_socket_mode", "must be a permission mode such as 0o660"));
+        }
+
+        if let Some(tls) = &self.server.tls {
+            if tls.reload_interval_secs == 0 {
+                return Err(invalid("server.tls.reload_interval_secs", "must be greater than 0"));
+            }
+
+            if let Some(acme) = &tls.acme {
+                if acme.domains.is_empty() {
+                    return Err(invalid("server.tls.acme.domains", "must list at least one domain"));
+                }
+
+                if tls.redirect_listen_address.is_none() {
+                    return Err(invalid("server.tls.redirect_listen_address", "is required to answer ACME HTTP-01 challenges"));
+                }
+
+                if acme.check_interval_secs == 0 {
+                    return Err(invalid("server.tls.acme.check_interval_secs", "must be greater than 0"));
+                }
+            }
+        }
+
+        let security = &self.server.security;
+        let header_values = [
+            ("server.security.referrer_policy", &security.referrer_policy),
+            ("server.security.permissions_policy", &security.permissions_policy),
+            ("server.security.content_security_policy", &security.content_security_policy),
+        ];
+        for (key, value) in header_values {
+            if HeaderValue::from_str(value).is_err() {
+                return Err(invalid(key, "must be a valid header value"));
+            }
+        }
+
+        if security.frame_ancestors.iter().any(|source| source.is_empty() || source.contains([';', ',']) || HeaderValue::from_str(source).is_err()) {
+            return Err(invalid("server.security.frame_ancestors", "must list CSP sources such as 'self' or https://example.com"));
+        }
+
+        let rate_limit = &self.server.rate_limit;
+        if rate_limit.max_clients == 0 {
+            return Err(invalid("server.rate_limit.max_clients", "must be greater than 0"));
+        }
+
+        for (route, limit) in &rate_limit.routes {
+            let key = format!("server.rate_limit.routes.\"{}\"", route);
+            if parse_route(route).is_none() {
+                return Err(invalid(&key, "must be a method and a path, e.g. \"POST /contact\""));
+            }
+            if limit.burst == 0 || limit.per_minute == 0 {
+                return Err(invalid(&key, "burst and per_minute must be greater than 0"));
+            }
+        }
+
+        let limits = &self.server.limits;
+        let positive = [
+            ("server.limits.request_timeout_ms", limits.request_timeout_ms),
+            ("server.limits.max_in_flight_requests", limits.max_in_flight_requests as u64),
+            ("server.limits.header_read_timeout_ms", limits.header_read_timeout_ms),
+            ("server.limits.max_connections", limits.max_connections as u64),
+        ];
+        for (key, value) in positive {
+            if value == 0 {
+                return Err(invalid(key, "must be greater than 0"));
+            }
+        }
+
+        for (route, timeout_ms) in &limits.route_timeouts_ms {
+            let key = format!("server.limits.route_timeouts_ms.\"{}\"", route);
+            if parse_route(route).is_none() {
+                return Err(invalid(&key, "must be a method and a path, e.g. \"POST /contact\""));
+            }
+            if *timeout_ms == 0 {
+                return Err(invalid(&key, "must be greater than 0"));
+            }
+        }
+
+        if self.database.max_connections == 0 {
+            return Err(invalid("database.max_connections", "must be greater than 0"));
+        }
+
+        if self.database.path.as_os_str().is_empty() {
+            return Err(invalid("database.path", "must not be empty"));
+        }
+
+        if EnvFilter::builder().parse(&self.logging.level).is_err() {
+            return Err(invalid("logging.level", "must be levels such as info,mywebsite=debug"));
+        }
+
+        if let Some(file) = &self.logging.file {
+            if file.prefix.is_empty() || file.prefix.contains('/') {
+                return Err(invalid("logging.file.prefix", "must be a file name"));
+            }
+            if file.max_files == 0 {
+                return Err(invalid("logging.file.max_files", "must be greater than 0"));
+            }
+        }
+
+        let telemetry = &self.telemetry;
+        if let Some(endpoint) = &telemetry.otlp_endpoint {
+            let uri = endpoint.parse::<Uri>().ok();
+            if !uri.is_some_and(|uri| matches!(uri.scheme_str(), Some("http" | "https")) && uri.host().is_some()) {
+                return Err(invalid("telemetry.otlp_endpoint", "must be an http or https URL"));
+            }
+        }
+
+        if telemetry.service_name.is_empty() {
+            return Err(invalid("telemetry.service_name", "must not be empty"));
+        }
+
+        if !(0.0..=1.0).contains(&telemetry.sample_ratio) {
+            return Err(invalid("telemetry.sample_ratio", "must be between 0 and 1"));
+        }
+
+        if matches!(self.storage.backend, StorageBackend::Json) && self.storage.json_path.as_os_str().is_empty() {
+            return Err(invalid("storage.json_path", "must not be empty"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits `"POST /contact"` into its method and path.
+pub fn parse_route(route: &str) -> Option<(Method, &str)> {
+    let (method, path) = route.split_once(' ')?;
+    let method = Method::from_str(method).ok()?;
+    path.starts_with('/').then_some((method, path))
+}
+
+fn invalid(key: &str, reason: &str) -> StartupError {
+    StartupError::InvalidSetting(key.to_string(), reason.to_string())
+}
+
+fn read_file(path: Option<&Path>) -> Result<Option<toml::Value>, StartupError> {
+    let (path, required) = match path {
+        Some(path) => (path, true),
+        None => (Path::new(DEFAULT_CONFIG_FILE), false),
+    };
+
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(StartupError::CannotReadConfigFile(path.to_path_buf(), e)),
+    };
+
+    let value = toml::from_str(&contents)
+        .map_err(|e| StartupError::CannotParseConfigFile(path.to_path_buf(), e))?;
+
+    Ok(Some(value))
+}
+
+/// Interprets a raw string as a TOML value so that `8080` or `true` keep their type,
+/// falling back to a plain string for things like addresses and paths.
+fn parse_value(raw: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("v = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("v"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+/// Tables of routes a layer replaces as a whole rather than merging into, so that leaving out a
+/// default route, e.g. `"POST /contact"`, removes it.
+const REPLACED_TABLES: [&str; 2] = ["server.rate_limit.routes", "server.limits.route_timeouts_ms"];
+
+fn merge(base: &mut toml::Value, layer: toml::Value, path: &str) {
+    match (base, layer) {
+        (toml::Value::Table(base), toml::Value::Table(layer)) if !REPLACED_TABLES.contains(&path) => {
+            for (key, value) in layer {
+                let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value, &path),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+fn set(root: &mut toml::Value, key: &str, value: toml::Value) -> Result<(), String> {
+    let segments = key_segments(key)?;
+    let Some((last, parents)) = segments.split_last() else {
+        return Err("empty key".to_string());
+    };
+
+    let mut current = root;
+    for part in parents {
+        let table = current.as_table_mut()
+            .ok_or_else(|| format!("cannot set {} inside a non-table value", part))?;
+
+        current = table
+            .entry(part.clone())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+    }
+
+    current.as_table_mut()
+        .ok_or_else(|| format!("cannot set {} inside a non-table value", last))?
+        .insert(last.clone(), value);
+    Ok(())
+}
+
+/// Splits a dotted key with TOML's own key syntax, so that segments containing dots or spaces
+/// can be quoted, e.g. `server.rate_limit.routes."POST /contact".burst`.
+fn key_segments(key: &str) -> Result<Vec<String>, String> {
+    let invalid = || format!("{:?} is not a valid dotted key", key);
+    let mut table = toml::from_str::<toml::Table>(&format!("{} = 0", key))
+        .map_err(|_| invalid())?;
+
+    let mut segments = Vec::new();
+    loop {
+        if table.len() != 1 {
+            return Err(invalid());
+        }
+        let (segment, value) = table.into_iter().next().ok_or_else(invalid)?;
+        segments.push(segment);
+        match value {
+            toml::Value::Table(inner) => table = inner,
+            toml::Value::Integer(0) => return Ok(segments),
+            _ => return Err(invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+    use std::path::PathBuf;
+    use super::{Config, RouteRateLimit, Sources};
+    use crate::app::error::StartupError;
+
+    /// A configuration file with these contents, unique to the calling test.
+    fn file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("mywebsite-config-{}-{}.toml", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(OsString, OsString)> {
+        vars.iter()
+            .map(|(name, value)| (OsString::from(name), OsString::from(value)))
+            .collect()
+    }
+
+    #[test]
+    fn later_layers_take_precedence() {
+        let file = file("precedence", r#"
+            [server]
+            body_limit = 100
+            shutdown_timeout_secs = 100
+
+            [database]
+            max_connections = 100
+        "#);
+        let sources = Sources {
+            file: Some(file.clone()),
+            env: env(&[
+                ("MYWEBSITE__SERVER__SHUTDOWN_TIMEOUT_SECS", "200"),
+                ("MYWEBSITE__DATABASE__MAX_CONNECTIONS", "200"),
+                ("OTHER__SERVER__BODY_LIMIT", "200"),
+            ]),
+            overrides: vec!["database.max_connections = 300".to_string()],
+        };
+
+        let config = Config::load(&sources).unwrap();
+        std::fs::remove_file(file).unwrap();
+
+        assert_eq!(config.server.body_limit, 100);
+        assert_eq!(config.server.shutdown_timeout_secs, 200);
+        assert_eq!(config.database.max_connections, 300);
+        assert_eq!(config.database.path, Config::default().database.path);
+    }
+
+    #[test]
+    fn route_tables_are_replaced_and_other_tables_merged() {
+        let file = file("routes", r#"
+            [server.security]
+            hsts_include_subdomains = true
+
+            [server.rate_limit.routes."POST /other"]
+            burst = 1
+            per_minute = 2
+        "#);
+        let sources = Sources {
+            file: Some(file.clone()),
+            ..Sources::default()
+        };
+
+        let config = Config::load(&sources).unwrap();
+        std::fs::remove_file(file).unwrap();
+
+        let routes = &config.server.rate_limit.routes;
+        assert_eq!(routes.keys().collect::<Vec<_>>(), ["POST /other"]);
+        assert_eq!(routes["POST /other"], RouteRateLimit { burst: 1, per_minute: 2, form_field: None });
+
+        let defaults = Config::default().server.security;
+        assert!(config.server.security.hsts_include_subdomains);
+        assert_eq!(config.server.security.hsts_max_age_secs, defaults.hsts_max_age_secs);
+    }
+
+    #[test]
+    fn overrides_accept_quoted_key_segments() {
+        let sources = Sources {
+            file: Some(file("quoted", "")),
+            overrides: vec![r#"server.rate_limit.routes."POST /contact".burst=5"#.to_string()],
+            ..Sources::default()
+        };
+
+        let config = Config::load(&sources).unwrap();
+        std::fs::remove_file(sources.file.unwrap()).unwrap();
+
+        let contact = &config.server.rate_limit.routes["POST /contact"];
+        assert_eq!(contact.burst, 5);
+        assert_eq!(contact.per_minute, Config::default().server.rate_limit.routes["POST /contact"].per_minute);
+    }
+
+    #[test]
+    fn only_prefixed_environment_variables_must_be_utf8() {
+        let not_utf8 = OsString::from_vec(vec![0xff, 0xfe]);
+        let file = file("utf8", "");
+
+        let ignored = Sources {
+            file: Some(file.clone()),
+            env: vec![(OsString::from("OTHER"), not_utf8.clone()), (not_utf8.clone(), OsString::from("1"))],
+            ..Sources::default()
+        };
+        assert!(Config::load(&ignored).is_ok());
+
+        let rejected = Sources {
+            file: Some(file.clone()),
+            env: vec![(OsString::from("MYWEBSITE__DATABASE__PATH"), not_utf8)],
+            ..Sources::default()
+        };
+        let result = Config::load(&rejected);
+        std::fs::remove_file(file).unwrap();
+
+        assert!(matches!(result, Err(StartupError::InvalidSetting(key, _)) if key == "MYWEBSITE__DATABASE__PATH"));
+    }
+}
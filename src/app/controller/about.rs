@@ -1,6 +1,8 @@
 use askama::Template;
-use http::StatusCode;
-use crate::app::controller::{EndpointResponse, MyError};
+use axum::extract::State;
+use axum::response::Response;
+use http::HeaderMap;
+use crate::app::controller::ControllerImpl;
 
 #[derive(Template)]
 #[template(path = "about.html")]
@@ -8,15 +10,10 @@ struct AboutView<'a> {
     current_page: &'a str,
 }
 
-pub async fn get_about() -> Result<EndpointResponse, MyError> {
-    let template = AboutView { current_page: "about" };
-    let body = template.render().map_err(MyError::RenderTemplateFailure)?;
-    let response = EndpointResponse {
-        status: StatusCode::OK,
-        content_type: "text/html",
-        should_cache: true,
-        body,
-    };
+pub fn render() -> askama::Result<String> {
+    AboutView { current_page: "about" }.render()
+}
 
-    Ok(response)
+pub async fn get_about<R>(State(c): State<ControllerImpl<R>>, headers: HeaderMap) -> Response {
+    c.pages.about.respond(&headers)
 }
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use axum::body::Body;
+use axum::response::{IntoResponse, Response};
+use http::{header, HeaderValue, StatusCode};
+use serde::Serialize;
+use crate::app::controller::encoding::{Encoded, Encoding};
+
+/// Identifies a cached /messages response.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub max_results: usize,
+    pub page_token: Option<String>,
+    pub encoding: Encoding,
+}
+
+/// A rendered and encoded /messages page.
+#[derive(Clone, Debug)]
+pub struct CachedPage {
+    body: Encoded,
+    last_modified: Option<SystemTime>,
+}
+
+impl CachedPage {
+    pub fn new(body: Encoded, last_modified: Option<SystemTime>) -> Self {
+        CachedPage { body, last_modified }
+    }
+
+    pub fn respond(&self) -> Response {
+        let mut headers = self.body.headers();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        if let Some(last_modified) = self.last_modified {
+            headers.insert(header::LAST_MODIFIED, httpdate::fmt_http_date(last_modified).parse().unwrap());
+        }
+
+        (StatusCode::OK, headers, Body::from(self.body.body())).into_response()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Stats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+/// Keeps rendered /messages pages for up to `ttl`, and drops all of them whenever a message is
+/// created.
+///
+/// Each invalidation starts a new generation. A page rendered from data read before an
+/// invalidation belongs to the previous generation and is not stored, so a request racing with
+/// a new message cannot put a stale page back in the cache.
+#[derive(Debug)]
+pub struct ResponseCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<Key, (Instant, CachedPage)>>,
+    generation: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ResponseCache {
+    /// A zero `ttl` or `max_entries` disables caching.
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        ResponseCache {
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+            generation: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, key: &Key) -> Option<CachedPage> {
+        let entries = self.entries.lock().unwrap();
+        let page = entries.get(key)
+            .filter(|(stored, _)| stored.elapsed() < self.ttl)
+            .map(|(_, page)| page.clone());
+
+        let counter = if page.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        page
+    }
+
+    /// Generation to pass to [`ResponseCache::insert`], to be read before reading the messages.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub fn insert(&self, key: Key, generation: u64, page: CachedPage) {
+        if self.ttl.is_zero() || self.max_entries == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if generation != self.generation() {
+            return;
+        }
+
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            entries.retain(|_, (stored, _)| stored.elapsed() < self.ttl);
+        }
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let oldest = entries.iter()
+                .min_by_key(|(_, (stored, _))| *stored)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(key, (Instant::now(), page));
+    }
+
+    pub fn invalidate(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        entries.clear();
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap().len(),
+        }
+    }
+}
@@ -2,11 +2,14 @@ use std::time::SystemTime;
 use askama::Template;
 use axum::extract::State;
 use axum::Form;
-use http::StatusCode;
+use axum::response::Response;
+use http::{HeaderMap, StatusCode};
 use serde::Deserialize;
 use crate::app::controller::{ControllerImpl, EndpointResponse, MyError};
 use crate::app::message::Message;
 use crate::app::message::repository::Repository;
+use crate::app::metrics::{metrics, Submission};
+use crate::app::validation;
 
 #[derive(Template)]
 #[template(path = "contact.html")]
@@ -21,43 +24,60 @@ pub struct ContactFormData {
     message: String,
 }
 
-pub async fn get_contact() -> Result<EndpointResponse, MyError> {
-    let template = ContactView { current_page: "contact" };
-    let body = template.render().map_err(MyError::RenderTemplateFailure)?;
-    let response = EndpointResponse {
-        status: StatusCode::OK,
-        content_type: "text/html",
-        should_cache: true,
-        body,
-    };
+pub fn render() -> askama::Result<String> {
+    ContactView { current_page: "contact" }.render()
+}
 
-    Ok(response)
+pub async fn get_contact<R>(State(c): State<ControllerImpl<R>>, headers: HeaderMap) -> Response {
+    c.pages.contact.respond(&headers)
 }
 
 pub async fn post_contact<R: Repository>(State(c): State<ControllerImpl<R>>, Form(form_data): Form<ContactFormData>) -> Result<EndpointResponse, MyError> {
+    if !c.features().contact_form {
+        let response = EndpointResponse {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            content_type: "text/html",
+            last_modified: None,
+            body: "<p>The contact form is closed for now, please try again later.</p>".to_string(),
+        };
+
+        metrics().contact_submission(Submission::Closed);
+        return Ok(response);
+    }
+
     let timestamp = SystemTime::now();
     let name = form_data.name.try_into()
-        .map_err(|e| MyError::InvalidField("name", e))?;
+        .map_err(|e| invalid("name", e))?;
 
     let email = form_data.email.try_into()
-        .map_err(|e| MyError::InvalidField("email", e))?;
+        .map_err(|e| invalid("email", e))?;
 
     let contents = form_data.message.try_into()
-        .map_err(|e| MyError::InvalidField("message", e))?;
+        .map_err(|e| invalid("message", e))?;
 
     let message = Message::new(timestamp, name, email, contents);
 
     c.repository.create(&message)
         .await
-        .map_err(|e| MyError::MessageRepositoryError(e))?;
+        .map_err(|e| {
+            metrics().contact_submission(Submission::Failed);
+            MyError::MessageRepositoryError(e)
+        })?;
+    c.messages_cache.invalidate();
+    metrics().contact_submission(Submission::Accepted);
 
     let body = "<p>Thank you for your message!</p>".to_string();
     let response = EndpointResponse {
         status: StatusCode::OK,
         content_type: "text/html",
-        should_cache: false,
+        last_modified: None,
         body,
     };
 
     Ok(response)
 }
+
+fn invalid(field: &'static str, e: validation::Error) -> MyError {
+    metrics().contact_submission(Submission::Invalid(field, &e));
+    MyError::InvalidField(field, e)
+}
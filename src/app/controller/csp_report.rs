@@ -0,0 +1,212 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::Json;
+use chrono::{DateTime, SecondsFormat, Utc};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use crate::app::controller::ControllerImpl;
+
+/// Distinct violations kept at most; past that, the one first seen longest ago is forgotten, so
+/// that anyone posting reports cannot grow the memory used without bound.
+const MAX_VIOLATIONS: usize = 1000;
+
+/// Longer URLs and directives are truncated before being stored.
+const MAX_FIELD_LENGTH: usize = 256;
+
+/// Largest report body accepted.
+pub const MAX_REPORT_BYTES: usize = 64 * 1024;
+
+/// What was blocked, where, and by which directive; reports of the same violation are counted
+/// together.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+pub struct Violation {
+    document: String,
+    directive: String,
+    blocked: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ViolationStats {
+    #[serde(flatten)]
+    violation: Violation,
+    /// Where the violation happened in the document, from the first report.
+    source: Option<String>,
+    count: u64,
+    first_seen: String,
+    last_seen: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    /// Most reported first.
+    violations: Vec<ViolationStats>,
+    /// Violations forgotten to make room for new ones.
+    evicted: u64,
+}
+
+#[derive(Debug, Default)]
+struct Reports {
+    violations: HashMap<Violation, ViolationStats>,
+    /// Keys of `violations`, in the order they were first reported.
+    order: VecDeque<Violation>,
+    evicted: u64,
+}
+
+/// Content-Security-Policy violations reported by browsers since startup.
+#[derive(Debug, Default)]
+pub struct CspReports {
+    state: Mutex<Reports>,
+}
+
+impl CspReports {
+    fn record(&self, violation: Violation, source: Option<String>) {
+        let now = timestamp(SystemTime::now());
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(stats) = state.violations.get_mut(&violation) {
+            stats.count += 1;
+            stats.last_seen = now;
+            return;
+        }
+
+        if state.order.len() >= MAX_VIOLATIONS {
+            if let Some(oldest) = state.order.pop_front() {
+                state.violations.remove(&oldest);
+                state.evicted += 1;
+            }
+        }
+
+        tracing::warn!(directive = %violation.directive, blocked = %violation.blocked, document = %violation.document, "CSP violation");
+        let stats = ViolationStats {
+            violation: violation.clone(),
+            source,
+            count: 1,
+            first_seen: now.clone(),
+            last_seen: now,
+        };
+        state.order.push_back(violation.clone());
+        state.violations.insert(violation, stats);
+    }
+
+    pub fn summary(&self) -> Summary {
+        let state = self.state.lock().unwrap();
+
+        let mut violations: Vec<ViolationStats> = state.violations.values().cloned().collect();
+        violations.sort_by_key(|stats| std::cmp::Reverse(stats.count));
+
+        Summary { violations, evicted: state.evicted }
+    }
+}
+
+/// Body of `report-uri` reports, sent as `application/csp-report`.
+#[derive(Debug, Deserialize)]
+struct LegacyReport {
+    #[serde(rename = "csp-report")]
+    report: LegacyViolation,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct LegacyViolation {
+    #[serde(default)]
+    document_uri: String,
+    #[serde(default)]
+    effective_directive: Option<String>,
+    #[serde(default)]
+    violated_directive: String,
+    #[serde(default)]
+    blocked_uri: String,
+    #[serde(default)]
+    source_file: Option<String>,
+    #[serde(default)]
+    line_number: Option<u64>,
+}
+
+/// One report of a `report-to` batch, sent as `application/reports+json`.
+#[derive(Debug, Deserialize)]
+struct Report {
+    #[serde(rename = "type")]
+    kind: String,
+    body: Option<ReportViolation>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportViolation {
+    #[serde(default, rename = "documentURL")]
+    document_url: String,
+    #[serde(default)]
+    effective_directive: String,
+    #[serde(default, rename = "blockedURL")]
+    blocked_url: String,
+    #[serde(default)]
+    source_file: Option<String>,
+    #[serde(default)]
+    line_number: Option<u64>,
+}
+
+/// Records violation reports in either format browsers send. The content type is not checked,
+/// as it differs between them.
+pub async fn post_csp_report<R>(State(c): State<ControllerImpl<R>>, body: Bytes) -> StatusCode {
+    if let Ok(LegacyReport { report }) = serde_json::from_slice(&body) {
+        let violation = Violation {
+            document: truncate(without_query(&report.document_uri)),
+            directive: truncate(report.effective_directive.as_deref().unwrap_or(&report.violated_directive)),
+            blocked: truncate(without_query(&report.blocked_uri)),
+        };
+        c.csp_reports.record(violation, location(report.source_file, report.line_number));
+        return StatusCode::NO_CONTENT;
+    }
+
+    let Ok(reports) = serde_json::from_slice::<Vec<Report>>(&body) else {
+        return StatusCode::BAD_REQUEST;
+    };
+
+    let violations = reports
+        .into_iter()
+        .filter(|report| report.kind == "csp-violation")
+        .filter_map(|report| report.body);
+    for report in violations {
+        let violation = Violation {
+            document: truncate(without_query(&report.document_url)),
+            directive: truncate(&report.effective_directive),
+            blocked: truncate(without_query(&report.blocked_url)),
+        };
+        c.csp_reports.record(violation, location(report.source_file, report.line_number));
+    }
+
+    StatusCode::NO_CONTENT
+}
+
+/// Violations reported so far, most frequent first.
+pub async fn get_csp_reports<R>(State(c): State<ControllerImpl<R>>) -> Json<Summary> {
+    Json(c.csp_reports.summary())
+}
+
+/// Query strings would split reports of the same violation apart, and may hold personal data.
+fn without_query(url: &str) -> &str {
+    url.split(['?', '#']).next().unwrap_or_default()
+}
+
+fn truncate(s: &str) -> String {
+    match s.char_indices().nth(MAX_FIELD_LENGTH) {
+        Some((end, _)) => s[..end].to_string(),
+        None => s.to_string(),
+    }
+}
+
+fn location(file: Option<String>, line: Option<u64>) -> Option<String> {
+    match (file, line) {
+        (Some(file), Some(line)) => Some(format!("{}:{}", truncate(without_query(&file)), line)),
+        (Some(file), None) => Some(truncate(without_query(&file))),
+        (None, _) => None,
+    }
+}
+
+fn timestamp(time: SystemTime) -> String {
+    let time: DateTime<Utc> = time.into();
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
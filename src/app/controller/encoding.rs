@@ -0,0 +1,177 @@
+use std::io::Write;
+use axum::body::Bytes;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use http::{header, HeaderMap, HeaderValue};
+use xxhash_rust::xxh3::xxh3_64;
+use crate::app::metrics::metrics;
+use crate::app::security;
+
+/// Content encodings offered for rendered pages, the same ones `CompressionLayer` offers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    Identity,
+    Brotli,
+    Zstd,
+    Gzip,
+    Deflate,
+}
+
+/// How hard to compress: `Best` for content compressed once, `Fast` for content compressed on
+/// the request path.
+#[derive(Clone, Copy, Debug)]
+pub enum Effort {
+    Best,
+    Fast,
+}
+
+impl Encoding {
+    /// In order of preference.
+    pub const ALL: [Encoding; 5] = [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip, Encoding::Deflate, Encoding::Identity];
+
+    fn name(self) -> Option<&'static str> {
+        match self {
+            Encoding::Identity => None,
+            Encoding::Brotli => Some("br"),
+            Encoding::Zstd => Some("zstd"),
+            Encoding::Gzip => Some("gzip"),
+            Encoding::Deflate => Some("deflate"),
+        }
+    }
+
+    /// The preferred encoding among the ones `Accept-Encoding` allows.
+    pub fn negotiate(request_headers: &HeaderMap) -> Encoding {
+        Encoding::ALL
+            .into_iter()
+            .find(|encoding| encoding.accepted(request_headers))
+            .unwrap_or(Encoding::Identity)
+    }
+
+    /// Whether `Accept-Encoding` lists this encoding, directly or through `*`, with a non-zero
+    /// quality. The uncompressed content is always acceptable.
+    pub fn accepted(self, request_headers: &HeaderMap) -> bool {
+        let Some(name) = self.name() else {
+            return true;
+        };
+
+        let accept_encoding = request_headers
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        let mut wildcard = false;
+        for item in accept_encoding.split(',') {
+            let mut parts = item.split(';');
+            let listed = parts.next().unwrap_or_default().trim();
+            let quality = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .filter_map(|q| q.parse::<f32>().ok())
+                .next()
+                .unwrap_or(1.0);
+
+            if listed.eq_ignore_ascii_case(name) {
+                return quality > 0.0;
+            }
+            if listed == "*" {
+                wildcard = quality > 0.0;
+            }
+        }
+
+        wildcard
+    }
+
+    fn compress(self, data: &[u8], effort: Effort) -> Vec<u8> {
+        let (level, brotli_quality, zstd_level) = match effort {
+            Effort::Best => (flate2::Compression::best(), 11, 19),
+            Effort::Fast => (flate2::Compression::fast(), 4, 3),
+        };
+
+        let compressed = match self {
+            Encoding::Identity => Ok(data.to_vec()),
+            Encoding::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, brotli_quality, 22);
+                encoder.write_all(data).map(|_| encoder.into_inner())
+            }
+            Encoding::Zstd => zstd::encode_all(data, zstd_level),
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), level);
+                encoder.write_all(data).and_then(|_| encoder.finish())
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), level);
+                encoder.write_all(data).and_then(|_| encoder.finish())
+            }
+        };
+
+        compressed.expect("can compress in memory")
+    }
+}
+
+/// A response body in one content encoding, with its ETag.
+///
+/// An uncompressed body using the CSP nonce gets the one of each request inserted when served.
+/// Its ETag is computed before that, so it is weak: responses differ only by their nonce.
+#[derive(Clone, Debug)]
+pub struct Encoded {
+    encoding: Encoding,
+    body: Bytes,
+    etag: HeaderValue,
+    nonce: bool,
+}
+
+impl Encoded {
+    pub fn new(encoding: Encoding, content: &[u8], effort: Effort) -> Encoded {
+        let body = encoding.compress(content, effort);
+        if let Some(name) = encoding.name() {
+            metrics().compression(name, content.len(), body.len());
+        }
+
+        let nonce = encoding == Encoding::Identity && security::has_nonce(&body);
+        let weak = if nonce { "W/" } else { "" };
+        let etag = HeaderValue::from_str(&format!("{}\"{:016x}\"", weak, xxh3_64(&body))).unwrap();
+
+        Encoded {
+            encoding,
+            body: Bytes::from(body),
+            etag,
+            nonce,
+        }
+    }
+
+    /// The smallest of `variants` the client accepts according to `Accept-Encoding`, which
+    /// must include the uncompressed one.
+    pub fn smallest_accepted<'a>(variants: &'a [Encoded], request_headers: &HeaderMap) -> &'a Encoded {
+        variants
+            .iter()
+            .filter(|v| v.encoding().accepted(request_headers))
+            .min_by_key(|v| v.len())
+            .expect("the uncompressed variant is always acceptable")
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn body(&self) -> Bytes {
+        if self.nonce {
+            security::insert_nonce(&self.body)
+        } else {
+            self.body.clone()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.body.len()
+    }
+
+    /// ETag, and Content-Encoding with Vary when compressed. `CompressionLayer` passes such
+    /// responses through, and adds Vary to the uncompressed ones itself.
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ETAG, self.etag.clone());
+        if let Some(name) = self.encoding.name() {
+            headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(name));
+            headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+        }
+        headers
+    }
+}
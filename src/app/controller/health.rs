@@ -0,0 +1,29 @@
+use axum::extract::State;
+use axum::Json;
+use http::{header, HeaderName, StatusCode};
+use crate::app::controller::cache::Stats;
+use crate::app::controller::{ControllerImpl, EndpointResponse, MyError};
+use crate::app::metrics;
+use crate::app::metrics::metrics;
+
+/// Liveness probe: answers as long as the server accepts and serves requests.
+pub async fn get_health() -> Result<EndpointResponse, MyError> {
+    let response = EndpointResponse {
+        status: StatusCode::OK,
+        content_type: "text/plain",
+        last_modified: None,
+        body: "ok\n".to_string(),
+    };
+
+    Ok(response)
+}
+
+/// Metrics for Prometheus to scrape.
+pub async fn get_metrics() -> ([(HeaderName, &'static str); 1], String) {
+    ([(header::CONTENT_TYPE, metrics::CONTENT_TYPE)], metrics().encode())
+}
+
+/// Hit and miss counters of the /messages cache.
+pub async fn get_cache_stats<R>(State(c): State<ControllerImpl<R>>) -> Json<Stats> {
+    Json(c.messages_cache.stats())
+}
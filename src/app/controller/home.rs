@@ -1,7 +1,8 @@
 use askama::Template;
 use axum::extract::State;
-use http::{StatusCode};
-use crate::app::controller::{Controller, EndpointResponse, MyError};
+use axum::response::Response;
+use http::HeaderMap;
+use crate::app::controller::ControllerImpl;
 
 #[derive(Template)]
 #[template(path = "home.html")]
@@ -9,16 +10,10 @@ struct HomeView<'a> {
     current_page: &'a str,
 }
 
+pub fn render() -> askama::Result<String> {
+    HomeView { current_page: "home" }.render()
+}
 
-pub async fn get_home<C: Controller>(State(_): State<C>) -> Result<EndpointResponse, MyError> {
-    let template = HomeView { current_page: "home" };
-    let body = template.render().map_err(MyError::RenderTemplateFailure)?;
-    let response = EndpointResponse {
-        status: StatusCode::OK,
-        content_type: "text/html",
-        should_cache: true,
-        body,
-    };
-
-    Ok(response)
+pub async fn get_home<R>(State(c): State<ControllerImpl<R>>, headers: HeaderMap) -> Response {
+    c.pages.home.respond(&headers)
 }
@@ -1,10 +1,14 @@
 use askama::Template;
 use axum::extract::{Query, State};
 use chrono::{DateTime, SecondsFormat, Utc};
-use http::StatusCode;
+use axum::response::Response;
+use http::HeaderMap;
 use serde::Deserialize;
-use crate::app::controller::{ControllerImpl, EndpointResponse, MyError};
+use crate::app::controller::cache::{CachedPage, Key};
+use crate::app::controller::encoding::{Effort, Encoded, Encoding};
+use crate::app::controller::{ControllerImpl, MyError};
 use crate::app::message::repository::Repository;
+use crate::app::security;
 
 #[derive(Template)]
 #[template(path = "messages.html")]
@@ -25,23 +29,57 @@ struct MessageEntry {
 }
 
 
+/// Renders a page with one message and a next page link, exercising every branch of the view.
+pub fn render_sample() -> askama::Result<String> {
+    MessagesView {
+        current_page: "messages",
+        entries: vec![MessageEntry {
+            time: "1970-01-01T00:00:00Z".to_string(),
+            name: "Sample".to_string(),
+            email: "sample@example.com".to_string(),
+            message: "Sample message".to_string(),
+        }],
+        max_results: 1,
+        has_next_page: true,
+        next_page_token: "1".to_string(),
+    }.render()
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ListMessageEntriesQuery {
     max_results: Option<usize>,
     page_token: Option<String>,
 }
 
-pub async fn get_messages<R: Repository>(State(c): State<ControllerImpl<R>>, Query(query): Query<ListMessageEntriesQuery>) -> Result<EndpointResponse, MyError> {
+pub async fn get_messages<R: Repository>(State(c): State<ControllerImpl<R>>, Query(query): Query<ListMessageEntriesQuery>, headers: HeaderMap) -> Result<Response, MyError> {
+    if !c.features().messages_page {
+        return Ok(c.pages.not_found.respond(&headers));
+    }
+
     let max_results = query.max_results.unwrap_or(10);
 
+    let key = Key {
+        max_results,
+        page_token: query.page_token.clone(),
+        encoding: Encoding::negotiate(&headers),
+    };
+
     let page_token = query.page_token
         .map(|s| s.try_into())
         .transpose()
         .map_err(|e| MyError::InvalidField("page_token", e))?;
 
+    if let Some(page) = c.messages_cache.get(&key) {
+        return Ok(page.respond());
+    }
+    let generation = c.messages_cache.generation();
+
     let (results, next_page_token) = c.repository.list(max_results, page_token)
         .await
-        .map_err(|e| MyError::MessageRepositoryError(e))?;
+        .map_err(MyError::MessageRepositoryError)?;
+
+    // A page only changes when one of its messages does, so the newest one dates it.
+    let last_modified = results.iter().map(|msg| msg.timestamp()).max();
 
     let results = results
         .into_iter()
@@ -59,22 +97,20 @@ pub async fn get_messages<R: Repository>(State(c): State<ControllerImpl<R>>, Que
     let template = MessagesView {
         current_page: "messages",
         entries: results,
-        max_results: query.max_results.unwrap_or(10),
+        max_results,
         has_next_page: next_page_token.is_some(),
         next_page_token: next_page_token.map(|p| p.to_string()).unwrap_or_default(),
     };
 
-    let body = template
-        .render()
+    let body = tracing::info_span!("render", template = "messages.html")
+        .in_scope(|| template.render())
         .map_err(MyError::RenderTemplateFailure)?;
 
-    let response = EndpointResponse {
-        status: StatusCode::OK,
-        content_type: "text/html",
-        should_cache: false,
-        body,
-    };
+    // The nonce is inserted into each response, which CompressionLayer compresses afterwards.
+    let encoding = if security::has_nonce(body.as_bytes()) { Encoding::Identity } else { key.encoding };
+    let page = CachedPage::new(Encoded::new(encoding, body.as_bytes(), Effort::Fast), last_modified);
+    let response = page.respond();
+    c.messages_cache.insert(key, generation, page);
 
     Ok(response)
 }
-
@@ -1,31 +1,94 @@
 mod home;
 mod about;
 mod not_found;
+mod unavailable;
 mod contact;
 mod messages;
+mod health;
+mod prerendered;
+pub(crate) mod encoding;
+mod cache;
+mod csp_report;
 
 use std::fmt::Display;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use axum::body::Body;
 use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
+use axum::extract::DefaultBodyLimit;
 use axum::Router;
 use axum::routing::{MethodRouter};
-use http::{header, HeaderMap};
+use http::{header, HeaderMap, HeaderName};
+use crate::app::config::FeaturesConfig;
 use crate::app::message::repository::Repository;
+use crate::app::controller::cache::ResponseCache;
+use crate::app::controller::csp_report::CspReports;
+use crate::app::controller::prerendered::Pages;
+use crate::app::reload::Settings;
+use crate::app::request_id::RequestId;
+use crate::app::controller::encoding::{Effort, Encoded, Encoding};
+use crate::app::security;
+use crate::app::security::CSP_REPORT_PATH;
 use crate::app::validation;
 
+/// Where the contact form posts messages.
+pub const CONTACT_PATH: &str = "/contact";
+
+/// Sent by HTMX on the requests it makes, whose responses are swapped into the current page.
+pub const HX_REQUEST: HeaderName = HeaderName::from_static("hx-request");
+
+/// Renders every view with sample data and returns the ones that fail, so template errors
+/// surface at startup rather than on the first request.
+pub fn check_views() -> Vec<(&'static str, askama::Error)> {
+    let views = [
+        ("home.html", home::render()),
+        ("about.html", about::render()),
+        ("contact.html", contact::render()),
+        ("messages.html", messages::render_sample()),
+        ("not_found.html", not_found::render()),
+        ("unavailable.html", unavailable::render()),
+    ];
+
+    views.into_iter()
+        .filter_map(|(name, rendered)| rendered.err().map(|e| (name, e)))
+        .collect()
+}
 
 pub trait Controller: Clone + Send + Sync  {
     fn router(&self) -> Router;
+
+    /// Operations endpoints, only served on the private `ops_listen_address`.
+    fn ops_router(&self) -> Router;
+
+    /// Page answered when the server is overloaded, without touching the handlers.
+    fn unavailable(&self, request_headers: &HeaderMap) -> Response;
 }
 
 #[derive(Debug, Clone)]
 pub struct ControllerImpl<R> {
     repository: R,
+    settings: Settings,
+    pages: Arc<Pages>,
+    messages_cache: Arc<ResponseCache>,
+    csp_reports: Arc<CspReports>,
 }
 
 impl<R> ControllerImpl<R> {
-    pub fn new(repository: R) -> ControllerImpl<R> {
-        ControllerImpl { repository }
+    /// Renders the pages that take no parameters up front, see [`Pages`].
+    pub fn new(repository: R, settings: Settings) -> askama::Result<ControllerImpl<R>> {
+        let pages = Arc::new(Pages::render()?);
+
+        let cache = settings.borrow().cache.clone();
+        let messages_cache = Arc::new(ResponseCache::new(Duration::from_millis(cache.ttl_ms), cache.max_entries));
+
+        let csp_reports = Arc::new(CspReports::default());
+
+        Ok(ControllerImpl { repository, settings, pages, messages_cache, csp_reports })
+    }
+
+    fn features(&self) -> FeaturesConfig {
+        self.settings.borrow().features.clone()
     }
 }
 
@@ -33,49 +96,98 @@ impl<R> Controller for ControllerImpl<R>
     where R: Repository + 'static {
     fn router(&self) -> Router {
         let home = MethodRouter::new()
-            .get(home::get_home::<Self>);
+            .get(home::get_home::<R>);
 
         let about = MethodRouter::new()
-            .get(about::get_about);
+            .get(about::get_about::<R>);
 
         let contact = MethodRouter::new()
-            .get(contact::get_contact)
+            .get(contact::get_contact::<R>)
             .post(contact::post_contact::<R>);
 
         let messages = MethodRouter::new()
             .get(messages::get_messages::<R>);
 
+        let csp_report = MethodRouter::new()
+            .post(csp_report::post_csp_report::<R>)
+            .layer(DefaultBodyLimit::max(csp_report::MAX_REPORT_BYTES));
+
         let not_found = MethodRouter::new()
-            .get(not_found::not_found);
+            .get(not_found::not_found::<R>);
 
         Router::new()
             .route("/", home)
             .route("/about", about)
-            .route("/contact", contact)
+            .route(CONTACT_PATH, contact)
             .route("/messages", messages)
+            .route(CSP_REPORT_PATH, csp_report)
             .fallback(not_found)
             .with_state(self.clone())
     }
+
+    fn ops_router(&self) -> Router {
+        let health = MethodRouter::new()
+            .get(health::get_health);
+
+        let cache = MethodRouter::new()
+            .get(health::get_cache_stats::<R>);
+
+        let csp_reports = MethodRouter::new()
+            .get(csp_report::get_csp_reports::<R>);
+
+        let metrics = MethodRouter::new()
+            .get(health::get_metrics);
+
+        Router::new()
+            .route("/healthz", health)
+            .route("/cache", cache)
+            .route("/csp-reports", csp_reports)
+            .route("/metrics", metrics)
+            .with_state(self.clone())
+    }
+
+    fn unavailable(&self, request_headers: &HeaderMap) -> Response {
+        // HTMX swaps the response into the form that was sent, which only needs the message.
+        if request_headers.contains_key(HX_REQUEST) {
+            let response = EndpointResponse {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                content_type: "text/html",
+                last_modified: None,
+                body: "<p>The server is busy right now, please try again in a few seconds.</p>".to_string(),
+            };
+            return response.into_response();
+        }
+
+        self.pages.unavailable.respond(request_headers)
+    }
 }
 
 struct EndpointResponse {
     status: StatusCode,
     content_type: &'static str,
-    should_cache: bool,
+    /// When the content last changed, if known; enables If-Modified-Since.
+    last_modified: Option<SystemTime>,
     body: String,
 }
 
 impl IntoResponse for EndpointResponse {
     fn into_response(self) -> axum::response::Response {
         let mut headers = HeaderMap::new();
-        if !self.should_cache {
-            headers.insert(header::CACHE_CONTROL, "no-cache".parse().unwrap());
-        } else {
-            headers.insert(header::CACHE_CONTROL, "public, max-age=604800".parse().unwrap());
-        }
+        headers.insert(header::CACHE_CONTROL, "no-cache".parse().unwrap());
 
         headers.insert(header::CONTENT_TYPE, self.content_type.parse().unwrap());
 
+        if let Some(last_modified) = self.last_modified {
+            headers.insert(header::LAST_MODIFIED, httpdate::fmt_http_date(last_modified).parse().unwrap());
+        }
+
+        // Tagged before the nonce goes in, so that the ETag is the same for every request.
+        if security::has_nonce(self.body.as_bytes()) {
+            let encoded = Encoded::new(Encoding::Identity, self.body.as_bytes(), Effort::Fast);
+            headers.extend(encoded.headers());
+            return (self.status, headers, Body::from(encoded.body())).into_response();
+        }
+
         (self.status, headers, self.body).into_response()
     }
 }
@@ -113,14 +225,22 @@ impl std::error::Error for MyError {
 }
 
 impl IntoResponse for MyError {
+    /// Errors are logged in the request's span, which holds its ID; visitors are shown the ID
+    /// in place of the details of internal errors.
     fn into_response(self) -> axum::response::Response {
+        let request_id = RequestId::current()
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+
         let (status, body) = match self {
-            MyError::RenderTemplateFailure(_) =>
-                (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()),
-            MyError::InvalidField(_, _) =>
-                (StatusCode::BAD_REQUEST, self.to_string()),
-            MyError::MessageRepositoryError(_) =>
-                (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()),
+            MyError::RenderTemplateFailure(_) | MyError::MessageRepositoryError(_) => {
+                tracing::error!(error = %self, "request failed");
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("internal error (request ID {})", request_id))
+            }
+            MyError::InvalidField(_, _) => {
+                tracing::info!(error = %self, "invalid request");
+                (StatusCode::BAD_REQUEST, self.to_string())
+            }
         };
 
         (status, body).into_response()
@@ -1,5 +1,8 @@
 use askama::Template;
-use crate::app::controller::{EndpointResponse, MyError};
+use axum::extract::State;
+use axum::response::Response;
+use http::HeaderMap;
+use crate::app::controller::ControllerImpl;
 
 #[derive(Template)]
 #[template(path = "not_found.html")]
@@ -7,15 +10,10 @@ struct NotFoundView<'a> {
     current_page: &'a str,
 }
 
-pub async fn not_found() -> Result<EndpointResponse, MyError> {
-    let template = NotFoundView { current_page: "not_found" };
-    let body = template.render().map_err(MyError::RenderTemplateFailure)?;
-    let response = EndpointResponse {
-        status: http::StatusCode::NOT_FOUND,
-        content_type: "text/html",
-        should_cache: true,
-        body,
-    };
+pub fn render() -> askama::Result<String> {
+    NotFoundView { current_page: "not_found" }.render()
+}
 
-    Ok(response)
+pub async fn not_found<R>(State(c): State<ControllerImpl<R>>, headers: HeaderMap) -> Response {
+    c.pages.not_found.respond(&headers)
 }
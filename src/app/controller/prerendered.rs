@@ -0,0 +1,65 @@
+use axum::body::Body;
+use axum::response::{IntoResponse, Response};
+use http::{header, HeaderMap, HeaderValue, StatusCode};
+use crate::app::controller::{about, contact, home, not_found, unavailable};
+use crate::app::controller::encoding::{Effort, Encoded, Encoding};
+use crate::app::security;
+
+/// Pages that take no parameters, rendered and compressed once at startup.
+#[derive(Debug)]
+pub struct Pages {
+    pub home: Page,
+    pub about: Page,
+    pub contact: Page,
+    pub not_found: Page,
+    pub unavailable: Page,
+}
+
+impl Pages {
+    pub fn render() -> askama::Result<Pages> {
+        Ok(Pages {
+            home: Page::new(StatusCode::OK, home::render())?,
+            about: Page::new(StatusCode::OK, about::render())?,
+            contact: Page::new(StatusCode::OK, contact::render())?,
+            not_found: Page::new(StatusCode::NOT_FOUND, not_found::render())?,
+            unavailable: Page::new(StatusCode::SERVICE_UNAVAILABLE, unavailable::render())?,
+        })
+    }
+}
+
+/// A rendered page, kept uncompressed and in every encoding `CompressionLayer` offers so that
+/// serving it costs no rendering nor compression. Pages using the CSP nonce are only kept
+/// uncompressed, see [`Encoded`].
+#[derive(Debug)]
+pub struct Page {
+    status: StatusCode,
+    variants: Vec<Encoded>,
+}
+
+impl Page {
+    fn new(status: StatusCode, html: askama::Result<String>) -> askama::Result<Page> {
+        let html = html?;
+        // The nonce is inserted into each response, which CompressionLayer compresses afterwards.
+        let encodings = if security::has_nonce(html.as_bytes()) { vec![Encoding::Identity] } else { Encoding::ALL.to_vec() };
+        let variants = encodings
+            .into_iter()
+            .map(|encoding| Encoded::new(encoding, html.as_bytes(), Effort::Best))
+            .collect();
+
+        Ok(Page { status, variants })
+    }
+
+    /// Serves the smallest variant the client accepts according to `Accept-Encoding`. The
+    /// uncompressed one is only chosen when the client accepts none of the encodings, so
+    /// `CompressionLayer` leaves it as is.
+    pub fn respond(&self, request_headers: &HeaderMap) -> Response {
+        let variant = Encoded::smallest_accepted(&self.variants, request_headers);
+
+        let mut headers = variant.headers();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+        // Revalidated on every visit, which their ETag makes cheap, so that a deploy shows at once.
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+
+        (self.status, headers, Body::from(variant.body())).into_response()
+    }
+}
@@ -0,0 +1,11 @@
+use askama::Template;
+
+#[derive(Template)]
+#[template(path = "unavailable.html")]
+struct UnavailableView<'a> {
+    current_page: &'a str,
+}
+
+pub fn render() -> askama::Result<String> {
+    UnavailableView { current_page: "unavailable" }.render()
+}
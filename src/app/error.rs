@@ -1,28 +1,116 @@
-use std::net::AddrParseError;
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::app::config::ListenAddress;
+use crate::app::message::repository;
+use crate::app::{migration, preflight};
+use crate::app::server::{acme, tls};
 
 #[derive(Debug)]
 pub enum StartupError {
-    CouldNotBind(std::io::Error),
-    InvalidListenAddress(AddrParseError),
+    CouldNotBind(ListenAddress, std::io::Error),
     CannotCreateConnectionPool(sqlx::Error),
-    CouldNotServe(std::io::Error),
+    CannotConfigureTls(tls::Error),
+    CannotObtainCertificate(acme::Error),
+    ShutdownDeadlineExceeded(Duration),
+    CouldNotCloseRepository(repository::Error),
+    CannotReadConfigFile(PathBuf, std::io::Error),
+    CannotParseConfigFile(PathBuf, toml::de::Error),
+    InvalidSetting(String, String),
+    InvalidOverride(String),
+    CannotMigrateDatabase(migration::Error),
+    SchemaDrift(Vec<migration::Drift>),
+    PreflightFailed(Vec<preflight::Problem>),
+    CannotRenderPages(askama::Error),
+    CannotCreateExportFile(PathBuf, std::io::Error),
+    CannotExportMessages(repository::Error),
+    CannotOpenImportFile(PathBuf, std::io::Error),
+    CannotImportMessages(repository::Error),
+    CannotImportIntoItself(PathBuf),
+    MemoryStorageNotSupported(&'static str),
+    CannotCreateLogDirectory(PathBuf, std::io::Error),
+    CannotOpenLogFile(PathBuf, tracing_appender::rolling::InitError),
+    CannotExportTraces(opentelemetry::trace::TraceError),
 }
 
 
 impl std::fmt::Display for StartupError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            StartupError::InvalidListenAddress(e) =>
-                write!(f, "invalid listen address: {}", e),
-
-            StartupError::CouldNotBind(e) =>
-                write!(f, "could not bind TCP server: {}", e),
+            StartupError::CouldNotBind(address, e) =>
+                write!(f, "could not bind {}: {}", address, e),
 
             StartupError::CannotCreateConnectionPool(e) =>
                 write!(f, "cannot create connection pool: {}", e),
 
-            StartupError::CouldNotServe(e) =>
-                write!(f, "could not serve: {}", e),
+            StartupError::CannotConfigureTls(e) =>
+                write!(f, "cannot configure TLS: {}", e),
+
+            StartupError::CannotObtainCertificate(e) =>
+                write!(f, "cannot obtain TLS certificate: {}", e),
+
+            StartupError::ShutdownDeadlineExceeded(deadline) =>
+                write!(f, "in-flight requests did not complete within {}s and were aborted", deadline.as_secs()),
+
+            StartupError::CouldNotCloseRepository(e) =>
+                write!(f, "could not close message repository: {}", e),
+
+            StartupError::CannotReadConfigFile(path, e) =>
+                write!(f, "cannot read config file {}: {}", path.display(), e),
+
+            StartupError::CannotParseConfigFile(path, e) =>
+                write!(f, "cannot parse config file {}: {}", path.display(), e),
+
+            StartupError::InvalidSetting(key, reason) =>
+                write!(f, "invalid setting {}: {}", key, reason),
+
+            StartupError::InvalidOverride(over) =>
+                write!(f, "invalid override {:?}: expected key=value", over),
+
+            StartupError::CannotMigrateDatabase(e) =>
+                write!(f, "cannot migrate database: {}", e),
+
+            StartupError::SchemaDrift(drift) => {
+                let drift = drift.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+                write!(f, "database schema does not match this version: {}", drift.join(", "))
+            }
+
+            StartupError::CannotRenderPages(e) =>
+                write!(f, "cannot render pages: {}", e),
+
+            StartupError::PreflightFailed(problems) => {
+                write!(f, "preflight checks failed:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+
+            StartupError::CannotCreateExportFile(path, e) =>
+                write!(f, "cannot create export file {}: {}", path.display(), e),
+
+            StartupError::CannotExportMessages(e) =>
+                write!(f, "cannot export messages: {}", e),
+
+            StartupError::CannotOpenImportFile(path, e) =>
+                write!(f, "cannot open import file {}: {}", path.display(), e),
+
+            StartupError::CannotImportMessages(e) =>
+                write!(f, "cannot import messages: {}", e),
+
+            StartupError::CannotImportIntoItself(path) =>
+                write!(f, "cannot import {} into itself: it is the configured storage.json_path", path.display()),
+
+            StartupError::MemoryStorageNotSupported(command) =>
+                write!(f, "cannot {} messages with the memory storage backend, which keeps nothing between runs", command),
+
+            StartupError::CannotCreateLogDirectory(path, e) =>
+                write!(f, "cannot create log directory {}: {}", path.display(), e),
+
+            StartupError::CannotOpenLogFile(path, e) =>
+                write!(f, "cannot open log file in {}: {}", path.display(), e),
+
+            StartupError::CannotExportTraces(e) =>
+                write!(f, "cannot export traces: {}", e),
         }
     }
 }
@@ -0,0 +1,232 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use http::{HeaderMap, HeaderName};
+use opentelemetry::{global, KeyValue};
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{runtime, Resource};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, TracerProvider};
+use tracing::Span;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use crate::app::config::{LogFileConfig, LogFormat, LogRotation, LoggingConfig, TelemetryConfig};
+use crate::app::error::StartupError;
+use crate::app::reload::Settings;
+use crate::app::request_id::RequestId;
+
+/// Whether spans are exported, in which case they carry the `otel.*` fields the exporter reads.
+static EXPORTING: AtomicBool = AtomicBool::new(false);
+
+/// The installed subscriber. Logs written to files and exported spans are sent in the
+/// background, and flushed when this is dropped, so it must live until the end of `serve`.
+#[derive(Debug)]
+pub struct Logging {
+    levels: Levels,
+    _guard: Option<WorkerGuard>,
+    tracer_provider: Option<TracerProvider>,
+}
+
+impl Logging {
+    pub fn levels(&self) -> Levels {
+        self.levels.clone()
+    }
+}
+
+impl Drop for Logging {
+    fn drop(&mut self) {
+        if let Some(provider) = &self.tracer_provider {
+            if let Err(e) = provider.shutdown() {
+                tracing::warn!(error = %e, "cannot export the last spans");
+            }
+        }
+    }
+}
+
+/// Changes the levels of the installed subscriber.
+#[derive(Clone, Debug)]
+pub struct Levels(reload::Handle<EnvFilter, Registry>);
+
+impl Levels {
+    /// Applies `logging.level` whenever the configuration is reloaded, until it no longer can be.
+    pub async fn follow(self, mut settings: Settings) {
+        while settings.changed().await.is_ok() {
+            let level = settings.borrow_and_update().logging.level.clone();
+            if let Err(e) = self.0.reload(filter(&level)) {
+                tracing::warn!(error = %e, "cannot change log levels");
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber, exporting spans when `telemetry.otlp_endpoint` is set. Levels
+/// are checked when the configuration is loaded, and apply to exported spans as well.
+pub fn init(config: &LoggingConfig, telemetry: &TelemetryConfig) -> Result<Logging, StartupError> {
+    let (writer, guard) = match &config.file {
+        Some(file) => {
+            let (writer, guard) = tracing_appender::non_blocking(appender(file)?);
+            (BoxMakeWriter::new(writer), Some(guard))
+        }
+        None => (BoxMakeWriter::new(std::io::stdout), None),
+    };
+
+    let (filter, levels) = reload::Layer::new(filter(&config.level));
+    let output = match config.format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer()
+            .pretty()
+            .with_ansi(config.file.is_none())
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_writer(writer)
+            .boxed(),
+    };
+
+    let tracer_provider = telemetry.otlp_endpoint
+        .as_ref()
+        .map(|endpoint| tracer_provider(endpoint, telemetry))
+        .transpose()
+        .map_err(StartupError::CannotExportTraces)?;
+    let spans = tracer_provider.as_ref().map(|provider| {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        tracing_opentelemetry::layer().with_tracer(provider.tracer("mywebsite"))
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(output)
+        .with(spans)
+        .init();
+    EXPORTING.store(tracer_provider.is_some(), Ordering::Relaxed);
+
+    Ok(Logging { levels: Levels(levels), _guard: guard, tracer_provider })
+}
+
+fn tracer_provider(endpoint: &str, config: &TelemetryConfig) -> Result<TracerProvider, TraceError> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+
+    // Requests that are part of a trace follow the sampling decision of their caller.
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_sampler(sampler)
+        .with_resource(Resource::new([KeyValue::new("service.name", config.service_name.clone())]))
+        .build();
+
+    Ok(provider)
+}
+
+fn appender(config: &LogFileConfig) -> Result<RollingFileAppender, StartupError> {
+    let rotation = match config.rotation {
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+
+    // The appender would create it on the first write, but looks for old files to delete first.
+    std::fs::create_dir_all(&config.directory)
+        .map_err(|e| StartupError::CannotCreateLogDirectory(config.directory.clone(), e))?;
+
+    RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(&config.prefix)
+        .max_log_files(config.max_files)
+        .build(&config.directory)
+        .map_err(|e| StartupError::CannotOpenLogFile(config.directory.clone(), e))
+}
+
+fn filter(level: &str) -> EnvFilter {
+    EnvFilter::builder().parse_lossy(level)
+}
+
+/// Span of a request for the trace layer. When exporting spans, it continues the trace of the
+/// W3C `traceparent` header, and is named by [`record_span_name`]. The route it matched is only
+/// known once routed, see [`record_route`].
+pub fn span<B>(request: &http::Request<B>) -> Span {
+    let id = request.extensions().get::<RequestId>().map(ToString::to_string).unwrap_or_default();
+    if !EXPORTING.load(Ordering::Relaxed) {
+        return tracing::info_span!(
+            "request",
+            method = %request.method(),
+            uri = %request.uri(),
+            version = ?request.version(),
+            route = tracing::field::Empty,
+            request_id = id,
+        );
+    }
+
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        route = tracing::field::Empty,
+        request_id = id,
+        otel.kind = "server",
+        otel.name = tracing::field::Empty,
+    );
+
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(request.headers())));
+    span.set_parent(parent);
+    span
+}
+
+/// Records the route template, e.g. `/messages`, in the request's span; unlike the URI it
+/// groups requests to the same handler together. It is also put in the response extensions, for
+/// the layers outside of the router.
+pub async fn record_route(request: Request, next: Next) -> Response {
+    let route = request.extensions().get::<MatchedPath>().cloned();
+    if let Some(route) = &route {
+        Span::current().record("route", route.as_str());
+    }
+
+    let mut response = next.run(request).await;
+    if let Some(route) = route {
+        response.extensions_mut().insert(route);
+    }
+    response
+}
+
+/// Names the exported span of a request after its method and route, e.g. `GET /messages`, or
+/// only its method when it matched none. Runs inside the trace layer, once the route is known.
+pub async fn record_span_name(request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let response = next.run(request).await;
+
+    if EXPORTING.load(Ordering::Relaxed) {
+        let name = match response.extensions().get::<MatchedPath>() {
+            Some(route) => format!("{} {}", method, route.as_str()),
+            None => method.to_string(),
+        };
+        Span::current().record("otel.name", name);
+    }
+    response
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
@@ -45,11 +45,11 @@ impl TryFrom<String> for Name {
     type Error = validation::Error;
 
     fn try_from(name: String) -> Result<Self, Self::Error> {
-        if name.len() < 1 {
+        if name.is_empty() {
             return Err(validation::Error::TooShort);
         }
 
-        if name.as_bytes().len() > 255 {
+        if name.len() > 255 {
             return Err(validation::Error::TooLong);
         }
 
@@ -71,11 +71,11 @@ impl TryFrom<String> for Email {
     type Error = validation::Error;
 
     fn try_from(email: String) -> Result<Self, Self::Error> {
-        if email.len() < 1 {
+        if email.is_empty() {
             return Err(validation::Error::TooShort);
         }
 
-        if email.as_bytes().len() > 255 {
+        if email.len() > 255 {
             return Err(validation::Error::TooLong);
         }
 
@@ -101,11 +101,11 @@ impl TryFrom<String> for Contents {
     type Error = validation::Error;
 
     fn try_from(message: String) -> Result<Self, Self::Error> {
-        if message.len() < 1 {
+        if message.is_empty() {
             return Err(validation::Error::TooShort);
         }
 
-        if message.as_bytes().len() > 1024 {
+        if message.len() > 1024 {
             return Err(validation::Error::TooLong);
         }
 
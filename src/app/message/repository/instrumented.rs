@@ -0,0 +1,47 @@
+use std::time::Instant;
+use tracing::Instrument;
+use crate::app::message::{Message, repository};
+use crate::app::message::model::PageToken;
+use crate::app::message::repository::Repository;
+use crate::app::metrics::metrics;
+
+/// Times `create` and `list` on the repository it wraps and counts their errors, see
+/// [`crate::app::metrics`]. Each call also gets a span, so traces tell storage time apart.
+#[derive(Clone, Debug)]
+pub struct InstrumentedRepository<R> {
+    inner: R,
+}
+
+impl<R> InstrumentedRepository<R> {
+    pub fn new(inner: R) -> Self {
+        InstrumentedRepository { inner }
+    }
+}
+
+impl<R: Repository> Repository for InstrumentedRepository<R> {
+    async fn create(&self, message: &Message) -> repository::Result<()> {
+        let start = Instant::now();
+        let result = self.inner.create(message)
+            .instrument(tracing::info_span!("repository.create"))
+            .await;
+        metrics().repository_operation("create", start.elapsed(), result.is_ok());
+        result
+    }
+
+    async fn list(&self, max_results: usize, page_token: Option<PageToken>) -> repository::Result<(Vec<Message>, Option<PageToken>)> {
+        let start = Instant::now();
+        let result = self.inner.list(max_results, page_token)
+            .instrument(tracing::info_span!("repository.list", max_results))
+            .await;
+        metrics().repository_operation("list", start.elapsed(), result.is_ok());
+        result
+    }
+
+    async fn check(&self) -> repository::Result<()> {
+        self.inner.check().await
+    }
+
+    async fn close(&self) -> repository::Result<()> {
+        self.inner.close().await
+    }
+}
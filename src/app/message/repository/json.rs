@@ -1,4 +1,5 @@
 use std::fmt::Display;
+use std::path::PathBuf;
 use serde_json::Deserializer;
 use tokio::fs;
 use tokio::fs::File;
@@ -14,13 +15,13 @@ const MAX_RESULTS: usize = 100;
 
 #[derive(Clone, Debug)]
 pub struct JSONRepository {
-    filename: String,
+    filename: PathBuf,
 }
 
 impl JSONRepository {
-    pub fn new(filename: &str) -> Self {
+    pub fn new(filename: impl Into<PathBuf>) -> Self {
         JSONRepository {
-            filename: filename.to_string(),
+            filename: filename.into(),
         }
     }
 }
@@ -29,7 +30,7 @@ impl Repository for JSONRepository {
     async fn create(&self, msg: &Message) -> repository::Result<()> {
         let msg_dto: MessageDTO = msg.into();
         let mut msg_json = serde_json::to_string(&msg_dto)
-            .map_err(|e| Error::CannotSerializeMessageToDatabase(e))?;
+            .map_err(Error::CannotSerializeMessageToDatabase)?;
         msg_json.push('\n');
 
         let mut file = File::options()
@@ -37,19 +38,19 @@ impl Repository for JSONRepository {
             .append(true)
             .open(&self.filename)
             .await
-            .map_err(|e| Error::CannotAppendDatabaseFile(e))?;
+            .map_err(Error::CannotAppendDatabaseFile)?;
 
         file
             .write_all(msg_json.as_bytes())
             .await
-            .map_err(|e| Error::CannotAppendDatabaseFile(e))?;
+            .map_err(Error::CannotAppendDatabaseFile)?;
 
         Ok(())
     }
 
     async fn list(&self, max_results: usize, page_token: Option<PageToken>) -> repository::Result<(Vec<Message>, Option<PageToken>)> {
             let max_results = match max_results {
-                v if v == 0 =>
+                0 =>
                     MAX_RESULTS,
                 v if v > MAX_RESULTS =>
                     MAX_RESULTS,
@@ -64,16 +65,18 @@ impl Repository for JSONRepository {
                     0,
             };
 
-            let database_contents = fs::read(&self.filename)
-                .await
-                .map_err(|e| Error::CannotReadDatabaseFile(e))?;
+            let database_contents = match fs::read(&self.filename).await {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(Error::CannotReadDatabaseFile(e).into()),
+            };
 
             let dtos = Deserializer::from_slice(&database_contents)
                 .into_iter::<MessageDTO>()
                 .skip(page_token)
                 .take(max_results)
                 .collect::<std::result::Result<Vec<MessageDTO>, _>>()
-                .map_err(|e| Error::CannotDeserializeMessageFromDatabase(e))?;
+                .map_err(Error::CannotDeserializeMessageFromDatabase)?;
 
             let messages = dtos
                 .into_iter()
@@ -88,6 +91,44 @@ impl Repository for JSONRepository {
 
             Ok((messages, next_page_token))
         }
+
+    async fn check(&self) -> repository::Result<()> {
+        self.list(1, None).await?;
+
+        match File::options().append(true).open(&self.filename).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                // `create` makes the file on the first message; make sure it will be able to.
+                File::options()
+                    .write(true)
+                    .create_new(true)
+                    .open(&self.filename)
+                    .await
+                    .map_err(Error::CannotAppendDatabaseFile)?;
+
+                fs::remove_file(&self.filename)
+                    .await
+                    .map_err(Error::CannotAppendDatabaseFile)?;
+
+                Ok(())
+            }
+            Err(e) => Err(Error::CannotAppendDatabaseFile(e).into()),
+        }
+    }
+
+    async fn close(&self) -> repository::Result<()> {
+        let file = match File::open(&self.filename).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(Error::CannotSyncDatabaseFile(e).into()),
+        };
+
+        file.sync_all()
+            .await
+            .map_err(Error::CannotSyncDatabaseFile)?;
+
+        Ok(())
+    }
 }
 
 mod dto {
@@ -118,7 +159,8 @@ mod dto {
         type Error = Error;
 
         fn try_into(self) -> Result<Message, Self::Error> {
-            let timestamp = self.timestamp.try_into().unwrap();
+            let timestamp = self.timestamp.try_into()
+                .map_err(|_| Error::CannotMapTimestampFromDatabase(self.timestamp))?;
 
             let timestamp = std::time::UNIX_EPOCH + std::time::Duration::from_nanos(timestamp);
 
@@ -141,11 +183,14 @@ mod dto {
 
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     CannotDeserializeMessageFromDatabase(serde_json::Error),
     CannotMapObjectFromDatabase(&'static str, validation::Error),
+    CannotMapTimestampFromDatabase(u128),
     CannotAppendDatabaseFile(std::io::Error),
     CannotReadDatabaseFile(std::io::Error),
+    CannotSyncDatabaseFile(std::io::Error),
     CannotSerializeMessageToDatabase(serde_json::Error),
 }
 
@@ -156,10 +201,14 @@ impl std::error::Error for Error {
                 Some(e),
             Error::CannotMapObjectFromDatabase(_, e) =>
                 Some(e),
+            Error::CannotMapTimestampFromDatabase(_) =>
+                None,
             Error::CannotAppendDatabaseFile(e) =>
                 Some(e),
             Error::CannotReadDatabaseFile(e) =>
                 Some(e),
+            Error::CannotSyncDatabaseFile(e) =>
+                Some(e),
             Error::CannotSerializeMessageToDatabase(e) =>
                 Some(e),
         }
@@ -175,10 +224,14 @@ impl Display for Error {
                 write!(f, "cannot append to database file: {}", e),
             Error::CannotReadDatabaseFile(e) =>
                 write!(f, "cannot read database file: {}", e),
+            Error::CannotSyncDatabaseFile(e) =>
+                write!(f, "cannot sync database file: {}", e),
             Error::CannotDeserializeMessageFromDatabase(e) =>
                 write!(f, "cannot deserialize message from database: {}", e),
             Error::CannotMapObjectFromDatabase(field, error) =>
                 write!(f, "cannot map object from database: field {}: {}", field, error),
+            Error::CannotMapTimestampFromDatabase(timestamp) =>
+                write!(f, "cannot map object from database: timestamp {} is out of range", timestamp),
         }
     }
 }
@@ -0,0 +1,63 @@
+use std::sync::{Arc, RwLock};
+use crate::app::message::{Message, repository};
+use crate::app::message::model::PageToken;
+use crate::app::message::repository::Repository;
+
+const MAX_RESULTS: usize = 100;
+
+/// Keeps messages in process memory; everything is lost when the server stops.
+#[derive(Clone, Debug, Default)]
+pub struct InMemoryRepository {
+    messages: Arc<RwLock<Vec<Message>>>,
+}
+
+impl InMemoryRepository {
+    pub fn new() -> Self {
+        InMemoryRepository::default()
+    }
+}
+
+impl Repository for InMemoryRepository {
+    async fn create(&self, message: &Message) -> repository::Result<()> {
+        self.messages
+            .write()
+            .unwrap()
+            .push(message.clone());
+
+        Ok(())
+    }
+
+    async fn list(&self, max_results: usize, page_token: Option<PageToken>) -> repository::Result<(Vec<Message>, Option<PageToken>)> {
+        let max_results = match max_results {
+            0 => MAX_RESULTS,
+            v => v.min(MAX_RESULTS),
+        };
+
+        let offset = page_token.map(|t| t.offset()).unwrap_or(0);
+
+        let messages: Vec<Message> = self.messages
+            .read()
+            .unwrap()
+            .iter()
+            .skip(offset)
+            .take(max_results)
+            .cloned()
+            .collect();
+
+        let next_page_token = if messages.len() < max_results {
+            None
+        } else {
+            Some(PageToken::new(offset + max_results))
+        };
+
+        Ok((messages, next_page_token))
+    }
+
+    async fn check(&self) -> repository::Result<()> {
+        Ok(())
+    }
+
+    async fn close(&self) -> repository::Result<()> {
+        Ok(())
+    }
+}
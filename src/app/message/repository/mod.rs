@@ -2,7 +2,10 @@ use crate::app::message::{Message, PageToken};
 
 pub mod sqlite;
 pub mod json;
+pub mod memory;
+pub mod instrumented;
 
+const COPY_BATCH_SIZE: usize = 100;
 
 pub type Error = Box<dyn std::error::Error + Send + Sync >;
 pub type Result<T> = std::result::Result<T, Error>;
@@ -11,4 +14,30 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub trait Repository: Clone + Sync  {
     async fn create(&self, message: &Message) -> Result<()>;
     async fn list(&self, max_results: usize, page_token: Option<PageToken>) -> Result<(Vec<Message>, Option<PageToken>)>;
+    /// Verifies the storage is reachable, readable and writable, without changing what it holds.
+    async fn check(&self) -> Result<()>;
+    /// Releases the underlying storage once the server stopped, making sure everything that was
+    /// written is durable.
+    async fn close(&self) -> Result<()>;
+}
+
+/// Copies every message of `source` into `destination`, page by page, and returns how many
+/// messages were copied.
+pub async fn copy<S: Repository, D: Repository>(source: &S, destination: &D) -> Result<usize> {
+    let mut copied = 0;
+    let mut page_token = None;
+
+    loop {
+        let (messages, next_page_token) = source.list(COPY_BATCH_SIZE, page_token).await?;
+
+        for message in &messages {
+            destination.create(message).await?;
+        }
+        copied += messages.len();
+
+        match next_page_token {
+            Some(token) => page_token = Some(token),
+            None => return Ok(copied),
+        }
+    }
 }
@@ -1,11 +1,13 @@
 use std::fmt::Display;
-use sqlx::{Pool, Sqlite, SqlitePool};
+use sqlx::{Pool, Sqlite, SqliteExecutor, SqlitePool};
 use crate::app::message::{Message, repository};
 use crate::app::message::model::PageToken;
-use crate::app::message::repository::{Repository};
+use crate::app::message::repository::{Repository, COPY_BATCH_SIZE};
 use crate::app::message::repository::sqlite::dto::MessageDTO;
 use crate::app::validation;
 
+const MAX_RESULTS: usize = 100;
+
 #[derive(Clone, Debug)]
 pub struct SQLiteRepository {
     pool: SqlitePool,
@@ -16,35 +18,77 @@ impl SQLiteRepository {
     pub fn new(pool: Pool<Sqlite>) -> Self {
         SQLiteRepository { pool }
     }
+
+    /// Copies every message of `source` in a single transaction, so that an import failing
+    /// halfway leaves the database as it was. Returns how many messages were imported.
+    pub async fn import<S: Repository>(&self, source: &S) -> repository::Result<usize> {
+        let mut tx = self.pool.begin()
+            .await
+            .map_err(Error::SqlxError)
+            .map_err(Box::new)?;
+
+        let mut imported = 0;
+        let mut page_token = None;
+        loop {
+            let (messages, next_page_token) = source.list(COPY_BATCH_SIZE, page_token).await?;
+
+            for message in &messages {
+                insert(&mut *tx, message).await?;
+            }
+            imported += messages.len();
+
+            match next_page_token {
+                Some(token) => page_token = Some(token),
+                None => break,
+            }
+        }
+
+        tx.commit()
+            .await
+            .map_err(Error::SqlxError)
+            .map_err(Box::new)?;
+
+        Ok(imported)
+    }
+}
+
+async fn insert(executor: impl SqliteExecutor<'_>, message: &Message) -> repository::Result<()> {
+    let timestamp: chrono::DateTime<chrono::Utc> = message.timestamp().into();
+    let name: String = message.name().to_string();
+    let email: String = message.email().to_string();
+    let contents: String = message.contents().to_string();
+
+    sqlx::query("
+        INSERT INTO message (timestamp, name, email, contents)
+        VALUES (?1, ?2, ?3, ?4)
+    ")
+        .bind(timestamp)
+        .bind(name)
+        .bind(email)
+        .bind(contents)
+        .execute(executor)
+        .await
+        .map_err(Error::SqlxError)
+        .map_err(Box::new)?;
+
+    Ok(())
 }
 
 impl Repository for SQLiteRepository {
     async fn create(&self, message: &Message) -> repository::Result<()> {
-        let message = message.clone();
-            let timestamp: chrono::DateTime<chrono::Utc> = message.timestamp().into();
-            let name: String = message.name().to_string();
-            let email: String = message.email().to_string();
-            let contents: String = message.contents().to_string();
-
-            sqlx::query("
-                INSERT INTO message (timestamp, name, email, contents)
-                VALUES (?1, ?2, ?3, ?4)
-            ")
-                .bind(timestamp)
-                .bind(name)
-                .bind(email)
-                .bind(contents)
-                .execute(&self.pool)
-                .await
-                .map_err(|e| Error::SqlxError(e))
-                .map_err(|e| Box::new(e))?;
-
-            Ok(())
+        insert(&self.pool, message).await
     }
 
     async fn list(&self, max_results: usize, page_token: Option<PageToken>) -> repository::Result<(Vec<Message>, Option<PageToken>)> {
-            let page_token: Option<i64> = page_token.map(|t| t.offset().try_into().unwrap());
-            let max_results: i64 = max_results.try_into().unwrap();
+            let page_token: Option<i64> = page_token
+                .map(|t| t.offset().try_into())
+                .transpose()
+                .map_err(Error::OutOfRange)
+                .map_err(Box::new)?;
+            let max_results = match max_results {
+                0 => MAX_RESULTS,
+                v => v.min(MAX_RESULTS),
+            };
 
             let rows: Vec<MessageDTO> = sqlx::query_as("
                 SELECT id, timestamp, name, email, contents
@@ -54,20 +98,21 @@ impl Repository for SQLiteRepository {
                 LIMIT ?2
             ")
                 .bind(page_token)
-                .bind(max_results)
+                .bind(max_results as i64)
                 .fetch_all(&self.pool)
                 .await
-                .unwrap();
+                .map_err(Error::SqlxError)
+                .map_err(Box::new)?;
 
-            let next_page_token = if rows.len() < max_results as usize {
+            let next_page_token = if rows.len() < max_results {
                 None
             } else {
                 rows
                     .last()
-                    .map(|r| {
-                        let id = r.id().try_into().unwrap();
-                        PageToken::new(id)
-                    })
+                    .map(|r| r.id().try_into().map(PageToken::new))
+                    .transpose()
+                    .map_err(Error::OutOfRange)
+                    .map_err(Box::new)?
             };
 
             let msgs = rows.into_iter()
@@ -76,6 +121,47 @@ impl Repository for SQLiteRepository {
 
             Ok((msgs, next_page_token))
         }
+
+    async fn check(&self) -> repository::Result<()> {
+        // Writing a row and rolling it back proves the database is writable and has the columns
+        // `create` and `list` rely on, without leaving anything behind.
+        let mut tx = self.pool.begin()
+            .await
+            .map_err(Error::SqlxError)
+            .map_err(Box::new)?;
+
+        sqlx::query("
+            INSERT INTO message (timestamp, name, email, contents)
+            VALUES (?1, '', '', '')
+        ")
+            .bind(chrono::Utc::now())
+            .execute(&mut *tx)
+            .await
+            .map_err(Error::SqlxError)
+            .map_err(Box::new)?;
+
+        sqlx::query("
+            SELECT id, timestamp, name, email, contents
+            FROM message
+            LIMIT 1
+        ")
+            .execute(&mut *tx)
+            .await
+            .map_err(Error::SqlxError)
+            .map_err(Box::new)?;
+
+        tx.rollback()
+            .await
+            .map_err(Error::SqlxError)
+            .map_err(Box::new)?;
+
+        Ok(())
+    }
+
+    async fn close(&self) -> repository::Result<()> {
+        self.pool.close().await;
+        Ok(())
+    }
 }
 
 mod dto {
@@ -106,14 +192,14 @@ mod dto {
             let timestamp = self.timestamp.into();
             let name = self.name
                 .try_into()
-                .map_err(|e| CouldNotMapDatabaseObject(e))?;
+                .map_err(CouldNotMapDatabaseObject)?;
             let email = self.email
                 .try_into()
-                .map_err(|e| CouldNotMapDatabaseObject(e))?;
+                .map_err(CouldNotMapDatabaseObject)?;
 
             let contents = self.contents
                 .try_into()
-                .map_err(|e| CouldNotMapDatabaseObject(e))?;
+                .map_err(CouldNotMapDatabaseObject)?;
 
             Ok(Message::new(timestamp, name, email, contents))
         }
@@ -123,9 +209,11 @@ mod dto {
 type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     SqlxError(sqlx::Error),
     CouldNotMapDatabaseObject(validation::Error),
+    OutOfRange(std::num::TryFromIntError),
 }
 
 impl std::error::Error for Error {
@@ -133,6 +221,7 @@ impl std::error::Error for Error {
         match self {
             Error::SqlxError(e) => Some(e),
             Error::CouldNotMapDatabaseObject(e) => Some(e),
+            Error::OutOfRange(e) => Some(e),
         }
     }
 }
@@ -144,6 +233,8 @@ impl Display for Error {
                 write!(f, "sqlx error: {}", e),
             Error::CouldNotMapDatabaseObject(e) =>
                 write!(f, "could not map database object: {}", e),
+            Error::OutOfRange(e) =>
+                write!(f, "value out of range: {}", e),
         }
     }
 }
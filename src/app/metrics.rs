@@ -0,0 +1,217 @@
+use std::sync::{LazyLock, OnceLock};
+use std::time::{Duration, Instant};
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use http::Method;
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{exponential_buckets, linear_buckets, Histogram};
+use prometheus_client::registry::Registry;
+use sqlx::SqlitePool;
+use crate::app::assets::STATIC_PREFIX;
+use crate::app::validation;
+
+/// Content type of [`Metrics::encode`].
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// The metrics of the process, updated by the parts they measure.
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+type HistogramFamily<L> = Family<L, Histogram, fn() -> Histogram>;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, EncodeLabelSet)]
+struct RequestLabels {
+    method: &'static str,
+    /// The route template, e.g. `/messages`, so that the number of series stays bounded.
+    route: String,
+    status: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, EncodeLabelSet)]
+struct OperationLabels {
+    operation: &'static str,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, EncodeLabelSet)]
+struct PoolLabels {
+    state: &'static str,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, EncodeLabelSet)]
+struct EncodingLabels {
+    encoding: &'static str,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, EncodeLabelSet)]
+struct SubmissionLabels {
+    outcome: &'static str,
+    field: &'static str,
+    reason: &'static str,
+}
+
+/// What became of a message submitted through the contact form.
+#[derive(Debug)]
+pub enum Submission<'a> {
+    Accepted,
+    /// The form is disabled by `features.contact_form`.
+    Closed,
+    Invalid(&'static str, &'a validation::Error),
+    /// The repository could not store it.
+    Failed,
+    /// The client or email address sent too many, see `server.rate_limit`.
+    RateLimited,
+}
+
+#[derive(Debug)]
+pub struct Metrics {
+    registry: Registry,
+    requests: Family<RequestLabels, Counter>,
+    request_duration: HistogramFamily<RequestLabels>,
+    repository_duration: HistogramFamily<OperationLabels>,
+    repository_errors: Family<OperationLabels, Counter>,
+    pool_connections: Family<PoolLabels, Gauge>,
+    pool: OnceLock<SqlitePool>,
+    compression_ratio: HistogramFamily<EncodingLabels>,
+    contact_submissions: Family<SubmissionLabels, Counter>,
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let mut registry = Registry::default();
+
+        let requests = Family::default();
+        registry.register("http_requests", "Requests answered by the public listeners", requests.clone());
+
+        let request_duration = HistogramFamily::new_with_constructor(latency_histogram as fn() -> Histogram);
+        registry.register("http_request_duration_seconds", "Time until the response headers were ready", request_duration.clone());
+
+        let repository_duration = HistogramFamily::new_with_constructor(latency_histogram as fn() -> Histogram);
+        registry.register("repository_operation_duration_seconds", "Time taken by message repository operations", repository_duration.clone());
+
+        let repository_errors = Family::default();
+        registry.register("repository_operation_errors", "Message repository operations that failed", repository_errors.clone());
+
+        let pool_connections = Family::default();
+        registry.register("sqlite_pool_connections", "Connections of the SQLite pool, by state", pool_connections.clone());
+
+        let compression_ratio = HistogramFamily::new_with_constructor(ratio_histogram as fn() -> Histogram);
+        registry.register("compression_ratio", "Compressed size over original size of pre-compressed pages and files", compression_ratio.clone());
+
+        let contact_submissions = Family::default();
+        registry.register("contact_submissions", "Messages submitted through the contact form, by outcome", contact_submissions.clone());
+
+        Metrics {
+            registry,
+            requests,
+            request_duration,
+            repository_duration,
+            repository_errors,
+            pool_connections,
+            pool: OnceLock::new(),
+            compression_ratio,
+            contact_submissions,
+        }
+    }
+
+    fn request(&self, method: &Method, route: String, status: u16, elapsed: Duration) {
+        let labels = RequestLabels { method: method_label(method), route, status };
+        self.requests.get_or_create(&labels).inc();
+        self.request_duration.get_or_create(&labels).observe(elapsed.as_secs_f64());
+    }
+
+    pub fn repository_operation(&self, operation: &'static str, elapsed: Duration, succeeded: bool) {
+        let labels = OperationLabels { operation };
+        self.repository_duration.get_or_create(&labels).observe(elapsed.as_secs_f64());
+        if !succeeded {
+            self.repository_errors.get_or_create(&labels).inc();
+        }
+    }
+
+    /// Reports the size of `pool` along with the other metrics.
+    pub fn watch_pool(&self, pool: SqlitePool) {
+        let _ = self.pool.set(pool);
+    }
+
+    pub fn compression(&self, encoding: &'static str, original: usize, compressed: usize) {
+        if original == 0 {
+            return;
+        }
+        let labels = EncodingLabels { encoding };
+        self.compression_ratio.get_or_create(&labels).observe(compressed as f64 / original as f64);
+    }
+
+    pub fn contact_submission(&self, submission: Submission) {
+        let labels = match submission {
+            Submission::Accepted => SubmissionLabels { outcome: "accepted", field: "", reason: "" },
+            Submission::Closed => SubmissionLabels { outcome: "closed", field: "", reason: "" },
+            Submission::Invalid(field, e) => SubmissionLabels { outcome: "invalid", field, reason: e.code() },
+            Submission::Failed => SubmissionLabels { outcome: "failed", field: "", reason: "" },
+            Submission::RateLimited => SubmissionLabels { outcome: "rate_limited", field: "", reason: "" },
+        };
+        self.contact_submissions.get_or_create(&labels).inc();
+    }
+
+    /// Every metric in the OpenMetrics text format, which Prometheus scrapes.
+    pub fn encode(&self) -> String {
+        if let Some(pool) = self.pool.get() {
+            let idle = pool.num_idle() as i64;
+            self.pool_connections.get_or_create(&PoolLabels { state: "idle" }).set(idle);
+            self.pool_connections.get_or_create(&PoolLabels { state: "in_use" }).set(i64::from(pool.size()) - idle);
+        }
+
+        let mut encoded = String::new();
+        prometheus_client::encoding::text::encode(&mut encoded, &self.registry)
+            .expect("writing to a string cannot fail");
+        encoded
+    }
+}
+
+/// From 1ms to about 16s.
+fn latency_histogram() -> Histogram {
+    Histogram::new(exponential_buckets(0.001, 2.0, 15))
+}
+
+fn ratio_histogram() -> Histogram {
+    Histogram::new(linear_buckets(0.1, 0.1, 10))
+}
+
+/// Methods outside of the standard ones are grouped, as anyone can make up new ones.
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::OPTIONS => "OPTIONS",
+        Method::PATCH => "PATCH",
+        _ => "other",
+    }
+}
+
+/// Counts requests and times them by route and status. The route is the one recorded in the
+/// response extensions by [`crate::app::logging::record_route`]; static files and requests no
+/// route matched are grouped.
+pub async fn track(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = request.method().clone();
+    let is_static = request.uri().path().starts_with(STATIC_PREFIX);
+
+    let response = next.run(request).await;
+
+    let route = match response.extensions().get::<MatchedPath>() {
+        Some(route) => route.as_str().to_string(),
+        None if is_static => format!("{}/*", STATIC_PREFIX),
+        None => "other".to_string(),
+    };
+    metrics().request(&method, route, response.status().as_u16(), start.elapsed());
+
+    response
+}
@@ -0,0 +1,351 @@
+use std::fmt::Display;
+use std::time::Instant;
+use sqlx::SqlitePool;
+
+const HISTORY_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS "flyway_schema_history" (
+        "installed_rank" INT NOT NULL PRIMARY KEY,
+        "version" VARCHAR(50),
+        "description" VARCHAR(200) NOT NULL,
+        "type" VARCHAR(20) NOT NULL,
+        "script" VARCHAR(1000) NOT NULL,
+        "checksum" INT,
+        "installed_by" VARCHAR(100) NOT NULL,
+        "installed_on" TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f','now')),
+        "execution_time" INT NOT NULL,
+        "success" BOOLEAN NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS "flyway_schema_history_s_idx" ON "flyway_schema_history" ("success");
+"#;
+
+const INSTALLED_BY: &str = "mywebsite";
+
+/// Schema migrations embedded in the binary, in the order they must be applied.
+/// They are the same scripts Flyway runs from `sql/sql`, and their history is kept in Flyway's
+/// `flyway_schema_history` table so both tools can be used on the same database.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Message",
+        script: "V1__Message.sql",
+        sql: include_str!("../../sql/sql/V1__Message.sql"),
+    },
+];
+
+#[derive(Debug)]
+pub struct Migration {
+    version: u32,
+    description: &'static str,
+    script: &'static str,
+    sql: &'static str,
+}
+
+impl Migration {
+    pub fn script(&self) -> &'static str {
+        self.script
+    }
+
+    /// Same checksum as Flyway: a CRC32 over every line, without line terminators.
+    pub fn checksum(&self) -> i32 {
+        let mut hasher = crc32fast::Hasher::new();
+        for line in self.sql.trim_start_matches('\u{feff}').lines() {
+            hasher.update(line.as_bytes());
+        }
+        hasher.finalize() as i32
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Migrator {
+    pool: SqlitePool,
+}
+
+impl Migrator {
+    pub fn new(pool: SqlitePool) -> Self {
+        Migrator { pool }
+    }
+
+    /// Applies every embedded migration that is not recorded in the history table yet and
+    /// returns the ones that were applied. Nothing is applied when the recorded history does not
+    /// match the embedded migrations, see [`Migrator::drift`].
+    pub async fn migrate(&self) -> Result<Vec<&'static Migration>> {
+        sqlx::raw_sql(HISTORY_TABLE)
+            .execute(&self.pool)
+            .await
+            .map_err(Error::QueryFailed)?;
+
+        let (pending, drift): (Vec<_>, Vec<_>) = self.drift()
+            .await?
+            .into_iter()
+            .partition(|d| matches!(d, Drift::Pending(_)));
+
+        if !drift.is_empty() {
+            return Err(Error::SchemaDrift(drift));
+        }
+
+        let mut newly_applied = Vec::new();
+        for drift in pending {
+            if let Drift::Pending(migration) = drift {
+                self.apply(migration).await?;
+                newly_applied.push(migration);
+            }
+        }
+
+        Ok(newly_applied)
+    }
+
+    /// Compares the history table with the embedded migrations without changing anything.
+    /// An empty result means the schema is exactly the one this binary expects.
+    pub async fn drift(&self) -> Result<Vec<Drift>> {
+        let history = self.history().await?;
+        let mut drift = Vec::new();
+
+        for entry in &history {
+            let migration = MIGRATIONS.iter().find(|m| m.version.to_string() == entry.version);
+            match migration {
+                _ if !entry.success =>
+                    drift.push(Drift::Failed(entry.script.clone())),
+                None =>
+                    drift.push(Drift::Unknown(entry.script.clone())),
+                Some(migration) if entry.checksum != Some(migration.checksum()) =>
+                    drift.push(Drift::Modified(migration, entry.checksum)),
+                Some(_) => {}
+            }
+        }
+
+        for migration in MIGRATIONS {
+            let applied = history.iter().any(|e| e.success && e.version == migration.version.to_string());
+            if !applied {
+                drift.push(Drift::Pending(migration));
+            }
+        }
+
+        Ok(drift)
+    }
+
+    /// Versioned entries of the history table, empty when the table does not exist.
+    async fn history(&self) -> Result<Vec<HistoryEntry>> {
+        let exists: bool = sqlx::query_scalar(r#"
+            SELECT COUNT(*) > 0
+            FROM "sqlite_master"
+            WHERE "type" = 'table' AND "name" = 'flyway_schema_history'
+        "#)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(Error::QueryFailed)?;
+
+        if !exists {
+            return Ok(Vec::new());
+        }
+
+        let rows: Vec<(String, String, Option<i32>, bool)> = sqlx::query_as(r#"
+            SELECT "version", "script", "checksum", "success"
+            FROM "flyway_schema_history"
+            WHERE "version" IS NOT NULL
+            ORDER BY "installed_rank"
+        "#)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(Error::QueryFailed)?;
+
+        Ok(rows.into_iter()
+            .map(|(version, script, checksum, success)| HistoryEntry { version, script, checksum, success })
+            .collect())
+    }
+
+    async fn apply(&self, migration: &Migration) -> Result<()> {
+        let mut tx = self.pool.begin().await.map_err(Error::QueryFailed)?;
+
+        let start = Instant::now();
+        sqlx::raw_sql(migration.sql)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Error::MigrationFailed(migration.script, e))?;
+        let execution_time: i64 = start.elapsed().as_millis().try_into().unwrap_or(i64::MAX);
+
+        sqlx::query(r#"
+            INSERT INTO "flyway_schema_history"
+                ("installed_rank", "version", "description", "type", "script", "checksum", "installed_by", "execution_time", "success")
+            SELECT COALESCE(MAX("installed_rank"), 0) + 1, ?1, ?2, 'SQL', ?3, ?4, ?5, ?6, 1
+            FROM "flyway_schema_history"
+        "#)
+            .bind(migration.version.to_string())
+            .bind(migration.description)
+            .bind(migration.script)
+            .bind(migration.checksum())
+            .bind(INSTALLED_BY)
+            .bind(execution_time)
+            .execute(&mut *tx)
+            .await
+            .map_err(Error::QueryFailed)?;
+
+        tx.commit().await.map_err(Error::QueryFailed)
+    }
+}
+
+struct HistoryEntry {
+    version: String,
+    script: String,
+    checksum: Option<i32>,
+    success: bool,
+}
+
+/// A difference between the schema history recorded in the database and the embedded migrations.
+#[derive(Debug)]
+pub enum Drift {
+    /// Embedded but not applied yet.
+    Pending(&'static Migration),
+    /// Applied, but the embedded script changed since.
+    Modified(&'static Migration, Option<i32>),
+    /// Applied, but not embedded in this binary, e.g. by a newer release.
+    Unknown(String),
+    /// Recorded as failed, the schema may be half-migrated.
+    Failed(String),
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Drift::Pending(migration) =>
+                write!(f, "{} is not applied", migration.script),
+            Drift::Modified(migration, Some(checksum)) =>
+                write!(f, "{} was modified after being applied (checksum {}, expected {})", migration.script, checksum, migration.checksum()),
+            Drift::Modified(migration, None) =>
+                write!(f, "{} was applied without a checksum", migration.script),
+            Drift::Unknown(script) =>
+                write!(f, "{} is applied but unknown to this version", script),
+            Drift::Failed(script) =>
+                write!(f, "{} failed in a previous run", script),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    QueryFailed(sqlx::Error),
+    MigrationFailed(&'static str, sqlx::Error),
+    SchemaDrift(Vec<Drift>),
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::QueryFailed(e) => Some(e),
+            Error::MigrationFailed(_, e) => Some(e),
+            Error::SchemaDrift(_) => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::QueryFailed(e) =>
+                write!(f, "query failed: {}", e),
+            Error::MigrationFailed(script, e) =>
+                write!(f, "migration {} failed: {}", script, e),
+            Error::SchemaDrift(drift) => {
+                let drift = drift.iter().map(Drift::to_string).collect::<Vec<_>>();
+                write!(f, "schema history does not match: {}", drift.join(", "))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+    use sqlx::SqlitePool;
+    use super::{Drift, Error, Migrator, MIGRATIONS};
+
+    /// An empty in-memory database. A single connection that is never recycled keeps it alive.
+    async fn database() -> SqlitePool {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
+    async fn execute(pool: &SqlitePool, sql: &str) {
+        sqlx::raw_sql(sql).execute(pool).await.unwrap();
+    }
+
+    #[test]
+    fn checksum_matches_flyway() {
+        // Recorded by Flyway for V1__Message.sql in db/database.sqlite.
+        assert_eq!(MIGRATIONS[0].checksum(), 1382881659);
+    }
+
+    #[tokio::test]
+    async fn applies_pending_migrations_once() {
+        let pool = database().await;
+        let migrator = Migrator::new(pool.clone());
+
+        let applied = migrator.migrate().await.unwrap();
+        assert_eq!(applied.iter().map(|m| m.script()).collect::<Vec<_>>(), ["V1__Message.sql"]);
+
+        let messages: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM message").fetch_one(&pool).await.unwrap();
+        assert_eq!(messages, 2);
+
+        assert!(migrator.drift().await.unwrap().is_empty());
+        assert!(migrator.migrate().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reports_pending_migrations_without_applying_them() {
+        let pool = database().await;
+
+        let drift = Migrator::new(pool.clone()).drift().await.unwrap();
+
+        assert!(matches!(drift[..], [Drift::Pending(m)] if m.script() == "V1__Message.sql"));
+        let tables: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table'").fetch_one(&pool).await.unwrap();
+        assert_eq!(tables, 0);
+    }
+
+    #[tokio::test]
+    async fn refuses_modified_migrations() {
+        let pool = database().await;
+        let migrator = Migrator::new(pool.clone());
+        migrator.migrate().await.unwrap();
+        execute(&pool, r#"UPDATE "flyway_schema_history" SET "checksum" = 42"#).await;
+
+        let result = migrator.migrate().await;
+
+        assert!(matches!(&result, Err(Error::SchemaDrift(drift)) if matches!(drift[..], [Drift::Modified(_, Some(42))])));
+    }
+
+    #[tokio::test]
+    async fn refuses_unknown_versions() {
+        let pool = database().await;
+        let migrator = Migrator::new(pool.clone());
+        migrator.migrate().await.unwrap();
+        execute(&pool, r#"
+            INSERT INTO "flyway_schema_history"
+                ("installed_rank", "version", "description", "type", "script", "checksum", "installed_by", "execution_time", "success")
+            VALUES (2, '2', 'Later', 'SQL', 'V2__Later.sql', 1, 'flyway', 0, 1)
+        "#).await;
+
+        let result = migrator.migrate().await;
+
+        assert!(matches!(&result, Err(Error::SchemaDrift(drift)) if matches!(&drift[..], [Drift::Unknown(script)] if script == "V2__Later.sql")));
+    }
+
+    #[tokio::test]
+    async fn refuses_to_continue_after_a_failed_migration() {
+        let pool = database().await;
+        let migrator = Migrator::new(pool.clone());
+        migrator.migrate().await.unwrap();
+        execute(&pool, r#"UPDATE "flyway_schema_history" SET "success" = 0"#).await;
+
+        let drift = migrator.drift().await.unwrap();
+        let result = migrator.migrate().await;
+
+        assert!(matches!(&drift[..], [Drift::Failed(script), Drift::Pending(_)] if script == "V1__Message.sql"));
+        assert!(matches!(result, Err(Error::SchemaDrift(drift)) if drift.len() == 1));
+    }
+}
@@ -1,5 +1,14 @@
+pub mod assets;
+pub mod config;
 pub mod error;
+pub mod logging;
 pub mod server;
 pub mod controller;
 pub mod message;
+pub mod metrics;
+pub mod migration;
+pub mod preflight;
+pub mod reload;
+pub mod request_id;
+pub mod security;
 pub mod validation;
@@ -0,0 +1,70 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+use crate::app::assets;
+use crate::app::config::ServerConfig;
+use crate::app::controller;
+use crate::app::error::StartupError;
+use crate::app::message::repository;
+use crate::app::message::repository::Repository;
+
+/// Something that would make requests fail once the server is up.
+#[derive(Debug)]
+pub enum Problem {
+    StaticDirUnreadable(PathBuf, std::io::Error),
+    StaticDirNotADirectory(PathBuf),
+    ViewFailed(&'static str, askama::Error),
+    StaticFileMissing(String),
+    StorageUnusable(repository::Error),
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Problem::StaticDirUnreadable(path, e) =>
+                write!(f, "static directory {}: {}", path.display(), e),
+            Problem::StaticDirNotADirectory(path) =>
+                write!(f, "static directory {}: not a directory", path.display()),
+            Problem::ViewFailed(view, e) =>
+                write!(f, "view {} does not render: {}", view, e),
+            Problem::StaticFileMissing(name) =>
+                write!(f, "static file {} is referenced by a view but missing", name),
+            Problem::StorageUnusable(e) =>
+                write!(f, "message storage: {}", e),
+        }
+    }
+}
+
+/// Checks everything requests depend on before the server starts accepting them, reporting
+/// every problem found rather than only the first one.
+pub async fn preflight<R: Repository>(config: &ServerConfig, repository: &R) -> Result<(), StartupError> {
+    let mut problems = Vec::new();
+
+    if let Some(static_dir) = &config.static_dir {
+        match std::fs::metadata(static_dir) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => problems.push(Problem::StaticDirNotADirectory(static_dir.clone())),
+            Err(e) => problems.push(Problem::StaticDirUnreadable(static_dir.clone(), e)),
+        }
+    }
+
+    let (failed, referenced) = assets::recording_references(controller::check_views);
+    for (view, e) in failed {
+        problems.push(Problem::ViewFailed(view, e));
+    }
+
+    for name in referenced {
+        if !assets::exists(config.static_dir.as_deref(), &name) {
+            problems.push(Problem::StaticFileMissing(name));
+        }
+    }
+
+    if let Err(e) = repository.check().await {
+        problems.push(Problem::StorageUnusable(e));
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(StartupError::PreflightFailed(problems))
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use crate::app::config::{Config, Sources};
+use crate::app::server::connection::stopping;
+
+/// The configuration currently in effect. Only its reloadable parts ever change, see
+/// [`Config::reloaded`].
+pub type Settings = watch::Receiver<Arc<Config>>;
+
+/// Re-reads the configuration on SIGHUP and publishes its reloadable parts to every [`Settings`].
+#[derive(Clone, Debug)]
+pub struct Reloader {
+    sources: Arc<Sources>,
+    current: Arc<watch::Sender<Arc<Config>>>,
+}
+
+impl Reloader {
+    pub fn new(sources: Sources, config: Config) -> Self {
+        let (current, _) = watch::channel(Arc::new(config));
+
+        Reloader {
+            sources: Arc::new(sources),
+            current: Arc::new(current),
+        }
+    }
+
+    pub fn subscribe(&self) -> Settings {
+        self.current.subscribe()
+    }
+
+    /// Loads the configuration again and swaps in its reloadable parts. An invalid configuration
+    /// is reported and the current one stays in effect.
+    pub fn reload(&self) {
+        let loaded = match Config::load(&self.sources) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                tracing::error!(error = %e, "cannot reload configuration, keeping the current one");
+                return;
+            }
+        };
+
+        let current = self.current.borrow().clone();
+        let reloaded = current.reloaded(&loaded);
+
+        let applied = diff(&current, &reloaded);
+        let ignored = diff(&reloaded, &loaded);
+
+        if applied.is_empty() && ignored.is_empty() {
+            tracing::info!("reloaded configuration, nothing changed");
+            return;
+        }
+
+        tracing::info!("reloaded configuration");
+        for (key, old, new) in &applied {
+            tracing::info!(key, old, new, "setting changed");
+        }
+        for (key, old, new) in &ignored {
+            tracing::warn!(key, old, new, "setting changed, ignored until restart");
+        }
+
+        if !applied.is_empty() {
+            self.current.send_replace(Arc::new(reloaded));
+        }
+    }
+
+    /// Reloads on every SIGHUP until `shutdown` flips to true.
+    pub async fn watch(self, mut shutdown: watch::Receiver<bool>) {
+        let mut hangup = signal(SignalKind::hangup())
+            .expect("can install SIGHUP handler");
+
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => self.reload(),
+                _ = stopping(&mut shutdown) => return,
+            }
+        }
+    }
+}
+
+/// Settings whose value differs between `old` and `new`, as `(key, old, new)`.
+fn diff(old: &Config, new: &Config) -> Vec<(String, String, String)> {
+    let old = flatten(old);
+    let new = flatten(new);
+
+    let mut keys = old.keys().chain(new.keys()).collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+
+    let missing = || "(unset)".to_string();
+    keys.into_iter()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .map(|key| {
+            let before = old.get(key).cloned().unwrap_or_else(missing);
+            let after = new.get(key).cloned().unwrap_or_else(missing);
+            (key.clone(), before, after)
+        })
+        .collect()
+}
+
+fn flatten(config: &Config) -> BTreeMap<String, String> {
+    fn walk(prefix: &str, value: toml::Value, into: &mut BTreeMap<String, String>) {
+        match value {
+            toml::Value::Table(table) =>
+                for (key, value) in table {
+                    let key = if prefix.is_empty() { key } else { format!("{}.{}", prefix, key) };
+                    walk(&key, value, into);
+                },
+            value => {
+                into.insert(prefix.to_string(), value.to_string());
+            }
+        }
+    }
+
+    let mut flattened = BTreeMap::new();
+    let value = toml::Value::try_from(config).expect("configuration is serializable");
+    walk("", value, &mut flattened);
+    flattened
+}
@@ -0,0 +1,65 @@
+use std::fmt::Display;
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use http::{HeaderName, HeaderValue};
+use rand::RngCore;
+
+/// Header carrying the ID, both on requests and responses.
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest incoming ID kept; longer ones are replaced.
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: RequestId;
+}
+
+/// Identifies a request across the logs, the response headers and the error pages, so that a
+/// visitor reporting an error can be matched with what the server recorded.
+#[derive(Clone, Debug)]
+pub struct RequestId(String);
+
+impl RequestId {
+    fn generate() -> RequestId {
+        let mut bytes = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        RequestId(URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    /// The ID set by a reverse proxy or client, when it is short and printable enough to be
+    /// logged and echoed as is.
+    fn incoming(request: &Request) -> Option<RequestId> {
+        let value = request.headers().get(&REQUEST_ID_HEADER)?.to_str().ok()?;
+        let valid = !value.is_empty()
+            && value.len() <= MAX_LENGTH
+            && value.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b));
+        valid.then(|| RequestId(value.to_string()))
+    }
+
+    /// The ID of the request being handled, if any.
+    pub fn current() -> Option<RequestId> {
+        REQUEST_ID.try_with(RequestId::clone).ok()
+    }
+}
+
+impl Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Gives each request an ID, or keeps the one it came with, and echoes it in the response.
+///
+/// It sits outside of the trace layer, which reads it from the request extensions to put it in
+/// the span.
+pub async fn request_id(mut request: Request, next: Next) -> Response {
+    let id = RequestId::incoming(&request).unwrap_or_else(RequestId::generate);
+    request.extensions_mut().insert(id.clone());
+
+    let mut response = REQUEST_ID.scope(id.clone(), next.run(request)).await;
+    response.headers_mut().insert(REQUEST_ID_HEADER, HeaderValue::from_str(&id.0).unwrap());
+    response
+}
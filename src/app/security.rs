@@ -0,0 +1,145 @@
+use std::sync::LazyLock;
+use axum::body::Bytes;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use http::{header, HeaderName, HeaderValue, StatusCode};
+use rand::RngCore;
+use crate::app::config::SecurityConfig;
+
+/// Where browsers send Content-Security-Policy violation reports.
+pub const CSP_REPORT_PATH: &str = "/csp-report";
+
+/// Name of the endpoint in `Reporting-Endpoints`, referenced by the `report-to` directive.
+const REPORT_GROUP: &str = "csp";
+
+/// Stands for the nonce in rendered pages, which may be rendered once and served many times.
+/// Each response gets it replaced with the nonce of its request, see [`insert_nonce`].
+///
+/// Random itself, so that markup slipped into a page cannot use it to be given the real nonce.
+static NONCE_PLACEHOLDER: LazyLock<String> = LazyLock::new(generate_nonce);
+
+tokio::task_local! {
+    static NONCE: String;
+}
+
+/// Nonce for templates, e.g. `nonce="{{ crate::app::security::nonce() }}"`, allowing an inline
+/// script or style under the Content-Security-Policy.
+///
+/// Pages using it are compressed on every response rather than once, which is why the current
+/// templates keep their styles and scripts in static files.
+#[allow(dead_code)]
+pub fn nonce() -> &'static str {
+    &NONCE_PLACEHOLDER
+}
+
+/// Whether `content` uses the nonce, so that it has to be completed on every response.
+pub fn has_nonce(content: &[u8]) -> bool {
+    content
+        .windows(NONCE_PLACEHOLDER.len())
+        .any(|window| window == NONCE_PLACEHOLDER.as_bytes())
+}
+
+/// `content` with the nonce of the current request in place of the one templates render. Outside
+/// of a request there is no policy for it to match, and `content` is returned as is.
+pub fn insert_nonce(content: &Bytes) -> Bytes {
+    let Ok(html) = std::str::from_utf8(content) else {
+        return content.clone();
+    };
+
+    NONCE.try_with(|nonce| Bytes::from(html.replace(NONCE_PLACEHOLDER.as_str(), nonce)))
+        .unwrap_or_else(|_| content.clone())
+}
+
+fn generate_nonce() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    // URL-safe so that templates can use it unescaped in attributes and JSON.
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// The security headers, prepared once from the configuration.
+#[derive(Clone, Debug)]
+pub struct SecurityHeaders {
+    /// Sent as is.
+    headers: Vec<(HeaderName, HeaderValue)>,
+    /// Policy with `{nonce}` placeholders, and the header it goes in.
+    csp: Option<(HeaderName, String)>,
+}
+
+impl SecurityHeaders {
+    /// `https` tells whether Strict-Transport-Security applies; browsers ignore it over HTTP.
+    /// Values are checked when the configuration is loaded.
+    pub fn new(config: &SecurityConfig, https: bool) -> SecurityHeaders {
+        let mut headers = vec![
+            (header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+            (HeaderName::from_static("reporting-endpoints"), HeaderValue::from_str(&format!("{}=\"{}\"", REPORT_GROUP, CSP_REPORT_PATH)).unwrap()),
+        ];
+
+        if https && config.hsts_max_age_secs > 0 {
+            let mut hsts = format!("max-age={}", config.hsts_max_age_secs);
+            if config.hsts_include_subdomains {
+                hsts.push_str("; includeSubDomains");
+            }
+            headers.push((header::STRICT_TRANSPORT_SECURITY, HeaderValue::from_str(&hsts).unwrap()));
+        }
+        if !config.referrer_policy.is_empty() {
+            headers.push((header::REFERRER_POLICY, HeaderValue::from_str(&config.referrer_policy).unwrap()));
+        }
+        if !config.permissions_policy.is_empty() {
+            headers.push((HeaderName::from_static("permissions-policy"), HeaderValue::from_str(&config.permissions_policy).unwrap()));
+        }
+
+        // Report-only policies cannot contain frame-ancestors, so it gets a policy of its own.
+        let frame_ancestors = match config.frame_ancestors.as_slice() {
+            [] => None,
+            sources => Some(format!("frame-ancestors {}", sources.join(" "))),
+        };
+
+        let policy = config.content_security_policy.trim().trim_end_matches(';');
+        let reporting = format!("report-uri {}; report-to {}", CSP_REPORT_PATH, REPORT_GROUP);
+        let csp = match (policy.is_empty(), config.csp_report_only, frame_ancestors) {
+            (true, _, None) => None,
+            (true, _, Some(frame_ancestors)) =>
+                Some((header::CONTENT_SECURITY_POLICY, format!("{}; {}", frame_ancestors, reporting))),
+            (false, false, None) =>
+                Some((header::CONTENT_SECURITY_POLICY, format!("{}; {}", policy, reporting))),
+            (false, false, Some(frame_ancestors)) =>
+                Some((header::CONTENT_SECURITY_POLICY, format!("{}; {}; {}", policy, frame_ancestors, reporting))),
+            (false, true, frame_ancestors) => {
+                if let Some(frame_ancestors) = frame_ancestors {
+                    let enforced = format!("{}; {}", frame_ancestors, reporting);
+                    headers.push((header::CONTENT_SECURITY_POLICY, HeaderValue::from_str(&enforced).unwrap()));
+                }
+                Some((header::CONTENT_SECURITY_POLICY_REPORT_ONLY, format!("{}; {}", policy, reporting)))
+            }
+        };
+
+        SecurityHeaders { headers, csp }
+    }
+}
+
+/// Gives each request a nonce for the pages it is answered with, and adds the security headers
+/// to its response.
+pub async fn security_headers(State(headers): State<SecurityHeaders>, request: Request, next: Next) -> Response {
+    let nonce = generate_nonce();
+    let mut response = NONCE.scope(nonce.clone(), next.run(request)).await;
+
+    let response_headers = response.headers_mut();
+    for (name, value) in &headers.headers {
+        response_headers.append(name, value.clone());
+    }
+
+    // A 304 updates the headers the browser stored with the page, whose body keeps the nonce it
+    // was sent with; a policy with a new one would block its inline scripts and styles.
+    if let Some((name, policy)) = &headers.csp {
+        if response.status() != StatusCode::NOT_MODIFIED {
+            let policy = policy.replace("{nonce}", &nonce);
+            response.headers_mut().insert(name, HeaderValue::from_str(&policy).unwrap());
+        }
+    }
+
+    response
+}
@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use axum::extract::{Path as UrlPath, State};
+use axum::routing::get;
+use axum::Router;
+use http::StatusCode;
+use instant_acme::{Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount, NewOrder, OrderStatus, RetryPolicy};
+use tokio::sync::watch;
+use crate::app::config::{AcmeConfig, TlsConfig};
+use crate::app::server::connection::stopping;
+use crate::app::server::tls::ReloadingCertResolver;
+
+pub const CHALLENGE_PATH: &str = "/.well-known/acme-challenge/:token";
+
+const ACCOUNT_FILE: &str = "account.json";
+
+/// Key authorizations of the HTTP-01 challenges in progress, by token.
+#[derive(Clone, Debug, Default)]
+pub struct Challenges(Arc<RwLock<HashMap<String, String>>>);
+
+impl Challenges {
+    /// Router answering `/.well-known/acme-challenge/<token>` for pending challenges.
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route(CHALLENGE_PATH, get(answer_challenge))
+            .with_state(self.clone())
+    }
+}
+
+async fn answer_challenge(State(challenges): State<Challenges>, UrlPath(token): UrlPath<String>) -> Result<String, StatusCode> {
+    challenges.0
+        .read()
+        .unwrap()
+        .get(&token)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Obtains certificates from an ACME directory for the configured domains and renews them
+/// before they expire. Certificates are written to the TLS certificate and key paths, where
+/// the TLS listener picks them up.
+#[derive(Clone, Debug)]
+pub struct CertificateManager {
+    config: AcmeConfig,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    challenges: Challenges,
+}
+
+impl CertificateManager {
+    pub fn new(config: AcmeConfig, tls: &TlsConfig) -> Self {
+        CertificateManager {
+            config,
+            cert_path: tls.cert_path.clone(),
+            key_path: tls.key_path.clone(),
+            challenges: Challenges::default(),
+        }
+    }
+
+    pub fn challenges(&self) -> &Challenges {
+        &self.challenges
+    }
+
+    /// Orders a new certificate unless the one on disk is valid for long enough.
+    /// Returns whether a new certificate was written.
+    pub async fn ensure_certificate(&self) -> Result<bool, Error> {
+        let renew_before = Duration::from_secs(self.config.renew_before_days * 24 * 60 * 60);
+
+        match expiry(&self.cert_path) {
+            Ok(expires) if expires > SystemTime::now() + renew_before => return Ok(false),
+            Ok(_) => tracing::info!(path = %self.cert_path.display(), "TLS certificate expires soon, renewing"),
+            Err(e) => tracing::info!(error = %e, "no usable TLS certificate, ordering one"),
+        }
+
+        self.order_certificate().await?;
+        Ok(true)
+    }
+
+    /// Checks the certificate every `check_interval_secs` until `shutdown` flips to true,
+    /// making `resolver` pick up renewed certificates right away.
+    pub async fn renew(self, resolver: Arc<ReloadingCertResolver>, mut shutdown: watch::Receiver<bool>) {
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.check_interval_secs));
+        ticker.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => match self.ensure_certificate().await {
+                    Ok(true) => resolver.reload_if_changed(),
+                    Ok(false) => {}
+                    Err(e) => tracing::error!(error = %e, "cannot renew TLS certificate"),
+                },
+                _ = stopping(&mut shutdown) => return,
+            }
+        }
+    }
+
+    async fn order_certificate(&self) -> Result<(), Error> {
+        let account = self.account().await?;
+
+        let identifiers = self.config.domains
+            .iter()
+            .map(|domain| Identifier::Dns(domain.clone()))
+            .collect::<Vec<_>>();
+
+        let mut order = account
+            .new_order(&NewOrder::new(&identifiers))
+            .await
+            .map_err(Error::Acme)?;
+
+        let mut tokens = Vec::new();
+        let mut authorizations = order.authorizations();
+        while let Some(authorization) = authorizations.next().await {
+            let mut authorization = authorization.map_err(Error::Acme)?;
+            match authorization.status {
+                AuthorizationStatus::Pending => {}
+                AuthorizationStatus::Valid => continue,
+                status => return Err(Error::UnexpectedAuthorizationStatus(status)),
+            }
+
+            let identifier = authorization.identifier().to_string();
+            let mut challenge = authorization
+                .challenge(ChallengeType::Http01)
+                .ok_or(Error::NoHttp01Challenge(identifier))?;
+
+            self.challenges.0
+                .write()
+                .unwrap()
+                .insert(challenge.token.clone(), challenge.key_authorization().as_str().to_string());
+            tokens.push(challenge.token.clone());
+
+            challenge.set_ready().await.map_err(Error::Acme)?;
+        }
+
+        let retries = RetryPolicy::new().timeout(Duration::from_secs(120));
+        let status = order.poll_ready(&retries).await;
+
+        {
+            let mut challenges = self.challenges.0.write().unwrap();
+            for token in tokens {
+                challenges.remove(&token);
+            }
+        }
+
+        match status.map_err(Error::Acme)? {
+            OrderStatus::Ready => {}
+            status => return Err(Error::UnexpectedOrderStatus(status)),
+        }
+
+        let key_pem = order.finalize().await.map_err(Error::Acme)?;
+        let cert_pem = order.poll_certificate(&retries).await.map_err(Error::Acme)?;
+
+        // Both are written before either is replaced, so that the certificate watcher does not
+        // find a new key next to the old certificate for longer than it takes to rename them.
+        let key_temporary = write_temporary(&self.key_path, key_pem.as_bytes())?;
+        let cert_temporary = write_temporary(&self.cert_path, cert_pem.as_bytes())?;
+        replace(&key_temporary, &self.key_path)?;
+        replace(&cert_temporary, &self.cert_path)?;
+        tracing::info!(domains = %self.config.domains.join(", "), "obtained TLS certificate");
+
+        Ok(())
+    }
+
+    /// Restores the ACME account from the state directory, registering a new one on first use.
+    async fn account(&self) -> Result<Account, Error> {
+        let builder = match &self.config.ca_root {
+            Some(ca_root) => Account::builder_with_root(ca_root),
+            None => Account::builder(),
+        }.map_err(Error::Acme)?;
+
+        let account_path = self.config.state_dir.join(ACCOUNT_FILE);
+        match std::fs::read(&account_path) {
+            Ok(contents) => {
+                let credentials: AccountCredentials = serde_json::from_slice(&contents)
+                    .map_err(|e| Error::InvalidAccountFile(account_path.clone(), e))?;
+
+                builder.from_credentials(credentials)
+                    .await
+                    .map_err(Error::Acme)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let contact = self.config.contact
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>();
+
+                let new_account = NewAccount {
+                    contact: &contact,
+                    terms_of_service_agreed: true,
+                    only_return_existing: false,
+                };
+
+                let (account, credentials) = builder
+                    .create(&new_account, self.config.directory_url.clone(), None)
+                    .await
+                    .map_err(Error::Acme)?;
+
+                let credentials = serde_json::to_vec_pretty(&credentials)
+                    .map_err(|e| Error::InvalidAccountFile(account_path.clone(), e))?;
+
+                std::fs::create_dir_all(&self.config.state_dir)
+                    .map_err(|e| Error::CannotWriteFile(self.config.state_dir.clone(), e))?;
+                write_private(&account_path, &credentials)?;
+                tracing::info!(directory_url = %self.config.directory_url, "registered ACME account");
+
+                Ok(account)
+            }
+            Err(e) => Err(Error::CannotReadFile(account_path, e)),
+        }
+    }
+}
+
+fn expiry(cert_path: &Path) -> Result<SystemTime, Error> {
+    let contents = std::fs::read(cert_path)
+        .map_err(|e| Error::CannotReadFile(cert_path.to_path_buf(), e))?;
+
+    let (_, pem) = x509_parser::pem::parse_x509_pem(&contents)
+        .map_err(|_| Error::InvalidCertificate(cert_path.to_path_buf()))?;
+    let certificate = pem.parse_x509()
+        .map_err(|_| Error::InvalidCertificate(cert_path.to_path_buf()))?;
+
+    let not_after = certificate.validity().not_after.timestamp();
+    let not_after = u64::try_from(not_after).unwrap_or(0);
+
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(not_after))
+}
+
+/// Replaces `path` atomically with a file only readable by the current user.
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    let temporary = write_temporary(path, contents)?;
+    replace(&temporary, path)
+}
+
+/// Writes `contents` next to `path`, readable by the owner only, and returns where.
+fn write_temporary(path: &Path, contents: &[u8]) -> Result<PathBuf, Error> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+
+    let mut file = std::fs::File::options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temporary)
+        .map_err(|e| Error::CannotWriteFile(temporary.clone(), e))?;
+
+    file.write_all(contents)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| Error::CannotWriteFile(temporary.clone(), e))?;
+
+    Ok(temporary)
+}
+
+fn replace(temporary: &Path, path: &Path) -> Result<(), Error> {
+    std::fs::rename(temporary, path)
+        .map_err(|e| Error::CannotWriteFile(path.to_path_buf(), e))
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Acme(instant_acme::Error),
+    CannotReadFile(PathBuf, std::io::Error),
+    CannotWriteFile(PathBuf, std::io::Error),
+    InvalidAccountFile(PathBuf, serde_json::Error),
+    InvalidCertificate(PathBuf),
+    NoHttp01Challenge(String),
+    UnexpectedAuthorizationStatus(AuthorizationStatus),
+    UnexpectedOrderStatus(OrderStatus),
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Acme(e) => Some(e),
+            Error::CannotReadFile(_, e) => Some(e),
+            Error::CannotWriteFile(_, e) => Some(e),
+            Error::InvalidAccountFile(_, e) => Some(e),
+            Error::InvalidCertificate(_) => None,
+            Error::NoHttp01Challenge(_) => None,
+            Error::UnexpectedAuthorizationStatus(_) => None,
+            Error::UnexpectedOrderStatus(_) => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Acme(e) =>
+                write!(f, "ACME error: {}", e),
+            Error::CannotReadFile(path, e) =>
+                write!(f, "cannot read {}: {}", path.display(), e),
+            Error::CannotWriteFile(path, e) =>
+                write!(f, "cannot write {}: {}", path.display(), e),
+            Error::InvalidAccountFile(path, e) =>
+                write!(f, "invalid ACME account file {}: {}", path.display(), e),
+            Error::InvalidCertificate(path) =>
+                write!(f, "cannot parse certificate {}", path.display()),
+            Error::NoHttp01Challenge(identifier) =>
+                write!(f, "no HTTP-01 challenge offered for {}", identifier),
+            Error::UnexpectedAuthorizationStatus(status) =>
+                write!(f, "unexpected authorization status {:?}", status),
+            Error::UnexpectedOrderStatus(status) =>
+                write!(f, "unexpected order status {:?}", status),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use tokio::net::TcpListener;
+    use crate::app::config::{AcmeConfig, TlsConfig};
+    use crate::app::server::tls::ReloadingCertResolver;
+    use super::{expiry, CertificateManager, ACCOUNT_FILE};
+
+    fn env_or(name: &str, default: &str) -> String {
+        std::env::var(name).unwrap_or_else(|_| default.to_string())
+    }
+
+    /// Runs against a Pebble instance, see scripts/pebble.sh:
+    /// `PEBBLE_CA_ROOT=pebble.minica.pem cargo test -- --ignored orders_and_renews_certificates_from_pebble`
+    #[tokio::test]
+    #[ignore = "needs a Pebble ACME server"]
+    async fn orders_and_renews_certificates_from_pebble() {
+        let directory = std::env::temp_dir().join(format!("mywebsite-acme-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let mut acme = AcmeConfig {
+            domains: vec!["localhost".to_string()],
+            contact: vec![],
+            directory_url: env_or("PEBBLE_DIRECTORY_URL", "https://localhost:14000/dir"),
+            ca_root: Some(PathBuf::from(env_or("PEBBLE_CA_ROOT", "pebble.minica.pem"))),
+            state_dir: directory.join("state"),
+            renew_before_days: 30,
+            check_interval_secs: 60,
+        };
+        let tls = TlsConfig {
+            cert_path: directory.join("cert.pem"),
+            key_path: directory.join("key.pem"),
+            reload_interval_secs: 60,
+            redirect_listen_address: None,
+            acme: None,
+        };
+
+        // Pebble validates HTTP-01 challenges on this port of the domain.
+        let manager = CertificateManager::new(acme.clone(), &tls);
+        let challenges = TcpListener::bind(format!("0.0.0.0:{}", env_or("PEBBLE_HTTP_PORT", "5002"))).await.unwrap();
+        let router = manager.challenges().router();
+        tokio::spawn(async move { axum::serve(challenges, router).await });
+
+        assert!(manager.ensure_certificate().await.unwrap(), "no certificate yet, one is ordered");
+        assert!(directory.join("state").join(ACCOUNT_FILE).is_file());
+        let key_mode = std::fs::metadata(&tls.key_path).unwrap().permissions().mode();
+        assert_eq!(key_mode & 0o777, 0o600);
+        ReloadingCertResolver::new(&tls).expect("certificate and key match");
+        let first = std::fs::read(&tls.cert_path).unwrap();
+
+        assert!(!manager.ensure_certificate().await.unwrap(), "a fresh certificate is kept");
+        assert_eq!(std::fs::read(&tls.cert_path).unwrap(), first);
+
+        // Renewing a century ahead makes the certificate on disk about to expire.
+        acme.renew_before_days = 36500;
+        let renewing = CertificateManager::new(acme, &tls);
+        assert!(renewing.ensure_certificate().await.unwrap(), "a certificate close to expiry is renewed");
+        assert_ne!(std::fs::read(&tls.cert_path).unwrap(), first);
+        assert!(expiry(&tls.cert_path).unwrap() > std::time::SystemTime::now());
+        ReloadingCertResolver::new(&tls).expect("renewed certificate and key match");
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
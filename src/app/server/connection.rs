@@ -0,0 +1,222 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use axum::extract::ConnectInfo;
+use axum::Router;
+use hyper::rt::Executor;
+use hyper_util::rt::{TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{watch, Semaphore};
+use tokio_rustls::TlsAcceptor;
+use tower_http::add_extension::AddExtension;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use crate::app::server::listener::Accept;
+
+/// Pause after a failed `accept`, typically because the process ran out of file descriptors.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Bounds on the connections of a listener.
+#[derive(Clone, Debug)]
+pub struct ConnectionLimits {
+    /// How long a client may take to complete the TLS handshake, and to send the headers of an
+    /// HTTP/1 request, so that slow clients cannot hold connections open for free.
+    pub header_read_timeout: Duration,
+    /// One permit per open connection, possibly shared with other listeners. Without a free
+    /// one, connections are left in the listen backlog.
+    pub slots: Option<Arc<Semaphore>>,
+}
+
+/// The tasks serving connections, and HTTP/2 streams within them. Shutdown waits for them to
+/// finish, and aborts them once its deadline has passed.
+#[derive(Clone, Debug, Default)]
+pub struct Tasks {
+    tracker: TaskTracker,
+    abort: CancellationToken,
+}
+
+impl Tasks {
+    pub fn spawn<F>(&self, task: F)
+        where F: Future<Output = ()> + Send + 'static {
+        let abort = self.abort.clone();
+        self.tracker.spawn(async move {
+            tokio::select! {
+                _ = task => {}
+                _ = abort.cancelled() => {}
+            }
+        });
+    }
+
+    /// Lets [`Tasks::wait`] return once every task has finished. Tasks can still be spawned.
+    pub fn close(&self) {
+        self.tracker.close();
+    }
+
+    pub async fn wait(&self) {
+        self.tracker.wait().await;
+    }
+
+    /// Drops every task at its next await point, which closes its connection, and waits until
+    /// they are all gone.
+    pub async fn abort(&self) {
+        self.abort.cancel();
+        self.tracker.wait().await;
+    }
+}
+
+impl<F> Executor<F> for Tasks
+    where F: Future + Send + 'static,
+          F::Output: Send + 'static {
+    fn execute(&self, future: F) {
+        self.spawn(async move {
+            future.await;
+        });
+    }
+}
+
+/// Accepts connections until `shutdown` flips to true, serving each of them on its own task
+/// of `tasks`. When `tls` is set, the TLS handshake happens on that task as well so a
+/// slow client cannot hold up the accept loop.
+///
+/// Requests carry the client address as a `ConnectInfo<Option<SocketAddr>>` extension, `None`
+/// on Unix domain sockets.
+pub async fn accept_loop<L: Accept>(
+    listener: L,
+    tls: Option<TlsAcceptor>,
+    app: Router,
+    limits: ConnectionLimits,
+    mut shutdown: watch::Receiver<bool>,
+    tasks: Tasks,
+) {
+    loop {
+        let slot = match &limits.slots {
+            Some(slots) => tokio::select! {
+                slot = slots.clone().acquire_owned() => slot.ok(),
+                _ = stopping(&mut shutdown) => return,
+            },
+            None => None,
+        };
+
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tracing::error!(error = %e, "could not accept connection");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            },
+            _ = stopping(&mut shutdown) => return,
+        };
+
+        let app = app.clone();
+        let shutdown = shutdown.clone();
+        let header_read_timeout = limits.header_read_timeout;
+        match tls.clone() {
+            None => {
+                let executor = tasks.clone();
+                tasks.spawn(async move {
+                    serve_connection(stream, peer, app, header_read_timeout, shutdown, executor).await;
+                    drop(slot);
+                });
+            }
+            Some(acceptor) => {
+                let executor = tasks.clone();
+                tasks.spawn(async move {
+                    // A client stalling the handshake is cut off like one stalling its headers.
+                    match tokio::time::timeout(header_read_timeout, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => serve_connection(stream, peer, app, header_read_timeout, shutdown, executor).await,
+                        Ok(Err(e)) => tracing::warn!(error = %e, "TLS handshake failed"),
+                        Err(_) => {}
+                    }
+                    drop(slot);
+                });
+            }
+        }
+    }
+}
+
+/// Serves HTTP/1 or HTTP/2 on `io`. Once `shutdown` flips to true the connection finishes its
+/// in-flight requests and then closes.
+async fn serve_connection<I>(io: I, peer: Option<SocketAddr>, app: Router, header_read_timeout: Duration, mut shutdown: watch::Receiver<bool>, executor: Tasks)
+    where I: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+    let mut builder = auto::Builder::new(executor);
+    builder.http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(header_read_timeout);
+    let connection = builder.serve_connection_with_upgrades(
+        TokioIo::new(io),
+        TowerToHyperService::new(AddExtension::new(app, ConnectInfo(peer))),
+    );
+    tokio::pin!(connection);
+
+    // Errors here are clients going away mid-request or speaking garbage; nothing to act on.
+    tokio::select! {
+        _ = connection.as_mut() => {}
+        _ = stopping(&mut shutdown) => {
+            connection.as_mut().graceful_shutdown();
+            let _ = connection.await;
+        }
+    }
+}
+
+/// Resolves once `shutdown` flips to true, or when the server side of the channel is gone.
+pub async fn stopping(shutdown: &mut watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stopping| *stopping).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use axum::Router;
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::watch;
+    use super::{accept_loop, ConnectionLimits, Tasks};
+
+    /// Records that the handler holding it was dropped.
+    struct Dropped(Arc<AtomicBool>);
+
+    impl Drop for Dropped {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn aborting_drops_requests_still_in_flight_after_shutdown() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let handler_dropped = dropped.clone();
+        let app = Router::new().route("/", get(move || async move {
+            let _dropped = Dropped(handler_dropped);
+            std::future::pending::<()>().await;
+        }));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let limits = ConnectionLimits { header_read_timeout: Duration::from_secs(5), slots: None };
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let tasks = Tasks::default();
+        tasks.spawn(accept_loop(listener, None, app, limits, shutdown_rx, tasks.clone()));
+
+        let mut client = TcpStream::connect(address).await.unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        shutdown_tx.send(true).unwrap();
+        tasks.close();
+        assert!(tokio::time::timeout(Duration::from_millis(50), tasks.wait()).await.is_err());
+
+        tokio::time::timeout(Duration::from_secs(1), tasks.abort()).await.unwrap();
+        assert!(dropped.load(Ordering::SeqCst));
+        let mut response = Vec::new();
+        let read = client.read_to_end(&mut response).await;
+        assert!(read.is_err() || response.is_empty(), "the connection is closed without a response");
+    }
+}
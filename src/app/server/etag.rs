@@ -0,0 +1,174 @@
+use std::time::SystemTime;
+use axum::body::{to_bytes, Body};
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use http::{HeaderMap, HeaderValue, Method, StatusCode};
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Gives successful GET and HEAD responses an ETag, and answers conditional requests with 304 Not
+/// Modified while the page did not change.
+///
+/// The hash is of the uncompressed body, while `CompressionLayer` may encode it afterwards, so the
+/// ETag is weak: the representations are equivalent but not byte for byte the same.
+///
+/// `If-None-Match` takes precedence; `If-Modified-Since` is only used without it, and only for
+/// responses carrying a Last-Modified date.
+pub async fn conditional_get(request: Request, next: Next) -> Response {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return next.run(request).await;
+    }
+
+    let if_none_match = request.headers().get(IF_NONE_MATCH).cloned();
+    let if_modified_since = http_date(request.headers(), IF_MODIFIED_SINCE);
+
+    let response = next.run(request).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+
+    // Responses may come with their ETag already, e.g. pre-rendered pages, which hash each
+    // encoding; others are hashed before being compressed.
+    let (mut parts, body) = response.into_parts();
+    let body = match parts.headers.get(ETAG) {
+        Some(_) => body,
+        None => {
+            let Ok(body) = to_bytes(body, usize::MAX).await else {
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            };
+
+            let etag = format!("W/\"{:016x}\"", xxh3_64(&body));
+            parts.headers.insert(ETAG, HeaderValue::from_str(&etag).unwrap());
+            Body::from(body)
+        }
+    };
+    let etag = parts.headers.get(ETAG).and_then(|etag| etag.to_str().ok()).unwrap_or_default();
+
+    let not_modified = match &if_none_match {
+        Some(if_none_match) => etag_matches(if_none_match, etag),
+        None => if_modified_since
+            .zip(http_date(&parts.headers, LAST_MODIFIED))
+            .is_some_and(|(since, modified)| modified <= since),
+    };
+
+    if not_modified {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(CONTENT_TYPE);
+        parts.headers.remove(CONTENT_LENGTH);
+        return Response::from_parts(parts, Body::empty());
+    }
+
+    Response::from_parts(parts, body)
+}
+
+/// Weak comparison, as RFC 9110 requires for If-None-Match.
+fn etag_matches(if_none_match: &HeaderValue, etag: &str) -> bool {
+    let Ok(if_none_match) = if_none_match.to_str() else {
+        return false;
+    };
+
+    let etag = opaque_tag(etag);
+    if_none_match.trim() == "*" || if_none_match
+        .split(',')
+        .map(|tag| opaque_tag(tag.trim()))
+        .any(|tag| tag == etag)
+}
+
+/// The tag without its weakness indicator.
+fn opaque_tag(etag: &str) -> &str {
+    etag.strip_prefix("W/").unwrap_or(etag)
+}
+
+fn http_date(headers: &HeaderMap, name: http::HeaderName) -> Option<SystemTime> {
+    headers.get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+    use axum::body::{to_bytes, Body};
+    use axum::middleware::from_fn;
+    use axum::routing::get;
+    use axum::Router;
+    use http::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+    use http::{HeaderValue, Request, StatusCode};
+    use tower::ServiceExt;
+    use super::{conditional_get, etag_matches};
+
+    fn modified() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    }
+
+    fn app() -> Router {
+        let page = || async { ([(LAST_MODIFIED, httpdate::fmt_http_date(modified()))], "<p>Hello</p>") };
+        Router::new()
+            .route("/", get(page).post(page))
+            .layer(from_fn(conditional_get))
+    }
+
+    async fn send(request: http::request::Builder) -> (StatusCode, Option<String>, usize) {
+        let response = app().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let etag = response.headers().get(ETAG).map(|etag| etag.to_str().unwrap().to_string());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, etag, body.len())
+    }
+
+    #[test]
+    fn weak_and_strong_tags_match() {
+        let matches = |if_none_match: &'static str, etag: &str| etag_matches(&HeaderValue::from_static(if_none_match), etag);
+
+        assert!(matches(r#"W/"abc""#, r#""abc""#));
+        assert!(matches(r#""abc""#, r#"W/"abc""#));
+        assert!(matches(r#""xyz", W/"abc""#, r#""abc""#));
+        assert!(matches("*", r#""abc""#));
+        assert!(!matches(r#""abcd""#, r#""abc""#));
+        assert!(!matches(r#"W/"xyz""#, r#"W/"abc""#));
+    }
+
+    #[tokio::test]
+    async fn answers_a_matching_if_none_match_with_304() {
+        let (status, etag, _) = send(Request::get("/")).await;
+        assert_eq!(status, StatusCode::OK);
+        let etag = etag.unwrap();
+        assert!(etag.starts_with("W/\""), "{}", etag);
+
+        let (status, not_modified_etag, length) = send(Request::get("/").header(IF_NONE_MATCH, &etag)).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        assert_eq!(not_modified_etag.as_ref(), Some(&etag));
+        assert_eq!(length, 0);
+
+        let strong = etag.trim_start_matches("W/");
+        let (status, _, _) = send(Request::get("/").header(IF_NONE_MATCH, strong)).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+
+        let (status, _, _) = send(Request::get("/").header(IF_NONE_MATCH, "\"other\"")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn answers_if_modified_since_with_304_unless_modified_later() {
+        let date = |time| httpdate::fmt_http_date(time);
+
+        let (status, _, _) = send(Request::get("/").header(IF_MODIFIED_SINCE, date(modified()))).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+
+        let earlier = modified() - Duration::from_secs(1);
+        let (status, _, _) = send(Request::get("/").header(IF_MODIFIED_SINCE, date(earlier))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // If-None-Match wins when both are sent.
+        let (status, _, _) = send(Request::get("/").header(IF_MODIFIED_SINCE, date(modified())).header(IF_NONE_MATCH, "\"other\"")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn leaves_other_methods_alone() {
+        let (status, etag, _) = send(Request::post("/").header(IF_NONE_MATCH, "*")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(etag, None);
+    }
+}
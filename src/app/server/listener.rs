@@ -0,0 +1,236 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::fs::DirBuilder;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use axum::Router;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::watch;
+use tokio_rustls::TlsAcceptor;
+use crate::app::config::ListenAddress;
+use crate::app::server::{connection, systemd};
+use crate::app::server::connection::{ConnectionLimits, Tasks};
+
+/// Same backlog as `tokio::net::TcpListener::bind`.
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// A bound socket accepting connections.
+pub trait Accept: Send + Sync + 'static {
+    type Io: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// The connection and, for TCP, the address of the client.
+    fn accept(&self) -> impl Future<Output = io::Result<(Self::Io, Option<SocketAddr>)>> + Send;
+}
+
+impl Accept for TcpListener {
+    type Io = tokio::net::TcpStream;
+
+    async fn accept(&self) -> io::Result<(Self::Io, Option<SocketAddr>)> {
+        let (stream, peer) = TcpListener::accept(self).await?;
+        Ok((stream, Some(peer)))
+    }
+}
+
+impl Accept for UnixListener {
+    type Io = tokio::net::UnixStream;
+
+    async fn accept(&self) -> io::Result<(Self::Io, Option<SocketAddr>)> {
+        let (stream, _) = UnixListener::accept(self).await?;
+        Ok((stream, None))
+    }
+}
+
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    /// With the socket file to remove once done, unless it was inherited from systemd.
+    Unix(UnixListener, Option<SocketFile>),
+}
+
+impl Listener {
+    /// Binds `address`. IPv6 TCP sockets only accept IPv6 connections, so `0.0.0.0:80` and
+    /// `[::]:80` can be listed side by side for dual-stack. Unix domain sockets get
+    /// `unix_socket_mode` permissions when set, and a socket file left behind by a previous run
+    /// is replaced.
+    pub async fn bind(address: &ListenAddress, unix_socket_mode: Option<u32>) -> io::Result<Listener> {
+        match address {
+            ListenAddress::Tcp(address) =>
+                bind_tcp(*address).map(Listener::Tcp),
+            ListenAddress::Unix(path) => {
+                let (listener, file) = bind_unix(path, unix_socket_mode)?;
+                Ok(Listener::Unix(listener, Some(file)))
+            }
+            ListenAddress::Systemd(name) =>
+                systemd::take_listener(name.as_deref()),
+        }
+    }
+
+    /// Serves `app` on this listener until `shutdown` flips to true, see
+    /// [`connection::accept_loop`].
+    pub fn spawn(self, tls: Option<TlsAcceptor>, app: Router, limits: ConnectionLimits, shutdown: watch::Receiver<bool>, tasks: &Tasks) {
+        match self {
+            Listener::Tcp(listener) =>
+                tasks.spawn(connection::accept_loop(listener, tls, app, limits, shutdown, tasks.clone())),
+            Listener::Unix(listener, file) => {
+                let accept_loop = connection::accept_loop(listener, tls, app, limits, shutdown, tasks.clone());
+                tasks.spawn(async move {
+                    accept_loop.await;
+                    drop(file);
+                })
+            }
+        };
+    }
+}
+
+fn bind_tcp(address: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    if address.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Binds in a private directory next to `path`, then moves the socket into place once it has
+/// its permissions, so that nobody can connect while it still has the default ones.
+fn bind_unix(path: &Path, mode: Option<u32>) -> io::Result<(UnixListener, SocketFile)> {
+    remove_stale_socket(path)?;
+
+    let private = path.with_file_name(format!(".mywebsite-{}", std::process::id()));
+    DirBuilder::new().mode(0o700).create(&private)?;
+    let bound = bind_and_move(&private.join("s"), path, mode);
+    let _ = std::fs::remove_dir_all(&private);
+    let listener = bound?;
+
+    let inode = std::fs::symlink_metadata(path)?.ino();
+    Ok((listener, SocketFile { path: path.to_path_buf(), inode }))
+}
+
+fn bind_and_move(temporary: &Path, path: &Path, mode: Option<u32>) -> io::Result<UnixListener> {
+    let listener = UnixListener::bind(temporary)?;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(temporary, std::fs::Permissions::from_mode(mode))?;
+    }
+    std::fs::rename(temporary, path)?;
+    Ok(listener)
+}
+
+/// Removes a socket file left behind by a process that is gone, which is when connecting to it
+/// is refused. A socket that still accepts connections belongs to a running server.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => match std::os::unix::net::UnixStream::connect(path) {
+            Ok(_) => Err(io::Error::new(io::ErrorKind::AddrInUse, "another process is listening on the socket")),
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => std::fs::remove_file(path),
+            Err(e) => Err(e),
+        },
+        Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, "path exists and is not a socket")),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// A socket file bound by this process, removed once its listener is done with it unless
+/// another process replaced it in the meantime.
+#[derive(Debug)]
+pub struct SocketFile {
+    path: PathBuf,
+    inode: u64,
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let ours = std::fs::symlink_metadata(&self.path).is_ok_and(|metadata| metadata.ino() == self.inode);
+        if ours {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use crate::app::config::ListenAddress;
+    use super::Listener;
+
+    /// An empty directory for the sockets of one test.
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("mywebsite-listener-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    async fn bind(path: &Path, mode: Option<u32>) -> io::Result<Listener> {
+        Listener::bind(&ListenAddress::Unix(path.to_path_buf()), mode).await
+    }
+
+    #[tokio::test]
+    async fn binds_with_the_configured_mode_and_removes_the_socket_when_done() {
+        let directory = directory("mode");
+        let path = directory.join("http.sock");
+
+        let listener = bind(&path, Some(0o660)).await.unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1, "the private directory is gone");
+
+        drop(listener);
+        assert!(!path.exists());
+        std::fs::remove_dir(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replaces_stale_sockets_but_not_live_ones() {
+        let directory = directory("stale");
+        let path = directory.join("http.sock");
+
+        let stale = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        drop(stale);
+        let live = bind(&path, None).await.unwrap();
+
+        let refused = bind(&path, None).await.unwrap_err();
+        assert_eq!(refused.kind(), io::ErrorKind::AddrInUse);
+        assert!(path.exists());
+
+        drop(live);
+        std::fs::remove_dir(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn leaves_a_socket_replaced_by_another_process() {
+        let directory = directory("replaced");
+        let path = directory.join("http.sock");
+
+        let listener = bind(&path, None).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let replacement = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        drop(listener);
+        assert!(path.exists());
+
+        drop(replacement);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_paths_that_are_not_sockets() {
+        let directory = directory("file");
+        let path = directory.join("http.sock");
+        std::fs::write(&path, "").unwrap();
+
+        let error = bind(&path, None).await.unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
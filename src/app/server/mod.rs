@@ -0,0 +1,664 @@
+pub mod acme;
+pub mod connection;
+mod etag;
+mod listener;
+mod overload;
+mod rate_limit;
+mod redirect;
+mod systemd;
+pub mod tls;
+
+use std::sync::Arc;
+use std::time::Duration;
+use http::{HeaderValue, Response, StatusCode};
+use hyper::header::CONTENT_LENGTH;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{watch, Semaphore};
+use tower::{ServiceBuilder};
+use tower::util::MapResponseLayer;
+use tower_http::compression::{CompressionBody, CompressionLayer};
+use crate::app::error::StartupError;
+use tower_http::services::fs::ServeDir;
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tower_http::LatencyUnit;
+use tracing::Level;
+use axum::body::{Body, HttpBody};
+use axum::Router;
+use tower_http::limit::{RequestBodyLimitLayer};
+use crate::app::assets;
+use crate::app::assets::STATIC_PREFIX;
+use crate::app::logging;
+use crate::app::metrics;
+use crate::app::request_id;
+use crate::app::security;
+use crate::app::security::SecurityHeaders;
+use crate::app::config::{ListenAddress, ServerConfig};
+use crate::app::controller::{Controller};
+use crate::app::server::acme::{CertificateManager, Challenges};
+use crate::app::server::connection::{ConnectionLimits, Tasks};
+use crate::app::server::listener::Listener;
+use crate::app::server::overload::Overload;
+use crate::app::server::rate_limit::RateLimiter;
+use crate::app::reload::Reloader;
+use crate::app::server::tls::ReloadingCertResolver;
+
+/// How the server stopped after receiving a shutdown signal.
+#[derive(Debug, Copy, Clone)]
+pub enum Shutdown {
+    /// Every in-flight request completed before the deadline.
+    Graceful,
+    /// The deadline passed while requests were still in flight; they were aborted.
+    DeadlineExceeded(Duration),
+}
+
+pub struct Server<C> {
+    config: ServerConfig,
+    controller: C,
+    reloader: Reloader,
+}
+
+impl<C> Server<C>
+    where C: Controller + 'static {
+    pub fn new(config: ServerConfig, controller: C, reloader: Reloader) -> Self {
+        Server {
+            config,
+            controller,
+            reloader,
+        }
+    }
+
+    /// Serves until SIGTERM or SIGINT, then stops accepting connections and waits up to
+    /// `shutdown_timeout_secs` for in-flight requests to complete before aborting them. SIGHUP
+    /// reloads the configuration meanwhile.
+    pub async fn run(&self) -> Result<Shutdown, StartupError> {
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let tasks = Tasks::default();
+
+        let certificates = self.config.tls
+            .as_ref()
+            .and_then(|tls| tls.acme.clone().map(|acme| CertificateManager::new(acme, tls)));
+        let challenges = certificates.as_ref().map(|c| c.challenges().clone());
+
+        // The ops listener stays reachable however busy the public ones are.
+        let public_limits = ConnectionLimits {
+            header_read_timeout: Duration::from_millis(self.config.limits.header_read_timeout_ms),
+            slots: Some(Arc::new(Semaphore::new(self.config.limits.max_connections))),
+        };
+        let ops_limits = ConnectionLimits { slots: None, ..public_limits.clone() };
+
+        let mut listeners = Vec::new();
+        for address in &self.config.listen_addresses {
+            listeners.push(self.bind(address).await?);
+        }
+
+        if let Some(address) = &self.config.ops_listen_address {
+            // Responses are only logged at debug level, health checks would drown out the rest.
+            let ops = self.controller.ops_router()
+                .route_layer(axum::middleware::from_fn(logging::record_route))
+                .layer(axum::middleware::from_fn(logging::record_span_name))
+                .layer(TraceLayer::new_for_http().make_span_with(logging::span))
+                .layer(axum::middleware::from_fn(request_id::request_id));
+            self.bind(address).await?.spawn(None, ops, ops_limits, shutdown_rx.clone(), &tasks);
+        }
+
+        if let Some(address) = self.config.tls.as_ref().and_then(|t| t.redirect_listen_address.as_ref()) {
+            let redirect_listener = self.bind(address).await?;
+
+            let https_port = self.config.listen_addresses
+                .iter()
+                .find_map(|address| match address {
+                    ListenAddress::Tcp(address) => Some(address.port()),
+                    _ => None,
+                })
+                .unwrap_or(443);
+
+            let mut redirect = redirect::router(https_port);
+            if let Some(challenges) = &challenges {
+                redirect = redirect.merge(challenges.router());
+            }
+
+            redirect_listener.spawn(None, redirect, public_limits.clone(), shutdown_rx.clone(), &tasks);
+        }
+
+        let acceptor = match &self.config.tls {
+            None => None,
+            Some(tls_config) => {
+                // The redirect listener is already answering HTTP-01 challenges at this point.
+                if let Some(certificates) = &certificates {
+                    certificates.ensure_certificate()
+                        .await
+                        .map_err(StartupError::CannotObtainCertificate)?;
+                }
+
+                let resolver = ReloadingCertResolver::new(tls_config)
+                    .map(Arc::new)
+                    .map_err(StartupError::CannotConfigureTls)?;
+
+                let interval = Duration::from_secs(tls_config.reload_interval_secs);
+                tokio::spawn(resolver.clone().watch(interval, shutdown_rx.clone()));
+
+                if let Some(certificates) = certificates {
+                    tokio::spawn(certificates.renew(resolver.clone(), shutdown_rx.clone()));
+                }
+
+                Some(tls::acceptor(resolver).map_err(StartupError::CannotConfigureTls)?)
+            }
+        };
+
+        let app = self.app(challenges.as_ref());
+        for listener in listeners {
+            listener.spawn(acceptor.clone(), app.clone(), public_limits.clone(), shutdown_rx.clone(), &tasks);
+        }
+
+        tokio::spawn(self.reloader.clone().watch(shutdown_rx.clone()));
+
+        shutdown_signal().await;
+        tracing::info!("shutting down, draining in-flight requests");
+        let _ = shutdown_tx.send(true);
+        tasks.close();
+
+        let timeout_secs = self.reloader.subscribe().borrow().server.shutdown_timeout_secs;
+        let deadline = Duration::from_secs(timeout_secs);
+        match tokio::time::timeout(deadline, tasks.wait()).await {
+            Ok(()) => Ok(Shutdown::Graceful),
+            Err(_) => {
+                tasks.abort().await;
+                Ok(Shutdown::DeadlineExceeded(deadline))
+            }
+        }
+    }
+
+    async fn bind(&self, address: &ListenAddress) -> Result<Listener, StartupError> {
+        Listener::bind(address, self.config.unix_socket_mode)
+            .await
+            .map_err(|e| StartupError::CouldNotBind(address.clone(), e))
+    }
+
+    fn app(&self, challenges: Option<&Challenges>) -> Router {
+        let compression = CompressionLayer::new()
+            .gzip(true)
+            .deflate(true)
+            .br(true)
+            .zstd(true);
+
+        let trace = TraceLayer::new_for_http()
+            .make_span_with(logging::span)
+            .on_response(DefaultOnResponse::new().level(Level::INFO).latency_unit(LatencyUnit::Millis));
+
+        // Sits outside compression so the length is the one of the bytes actually sent;
+        // compressed bodies are streamed and keep chunked framing.
+        let content_length = MapResponseLayer::new(content_length);
+
+        let security = SecurityHeaders::new(&self.config.security, self.config.tls.is_some());
+        let overload = Overload::new(&self.config.limits, self.controller.clone());
+
+        let middlewares = ServiceBuilder::new()
+            .layer(axum::middleware::from_fn(request_id::request_id))
+            .layer(trace)
+            .layer(axum::middleware::from_fn(logging::record_span_name))
+            .layer(axum::middleware::from_fn(metrics::track))
+            .layer(axum::middleware::from_fn_with_state(security, security::security_headers))
+            .layer(axum::middleware::from_fn_with_state(overload, overload::shed::<C>))
+            .layer(RequestBodyLimitLayer::new(self.config.body_limit))
+            .layer(content_length)
+            .layer(compression);
+
+        // Files from static_dir get Last-Modified from ServeDir; rendered pages and embedded
+        // files get an ETag. The route is recorded before the rate limiter runs, so that 429s
+        // are counted under it.
+        let rate_limiter = RateLimiter::new(self.reloader.subscribe());
+        let mut router = self.controller.router()
+            .route_layer(axum::middleware::from_fn_with_state(rate_limiter, rate_limit::rate_limit))
+            .route_layer(axum::middleware::from_fn(logging::record_route))
+            .layer(axum::middleware::from_fn(etag::conditional_get));
+        if let Some(challenges) = challenges {
+            router = router.merge(challenges.router());
+        }
+
+        let immutable = HeaderValue::from_static("public, max-age=31536000, immutable");
+        let static_files = match &self.config.static_dir {
+            Some(static_dir) => Router::new()
+                .fallback_service(ServeDir::new(static_dir).precompressed_br().precompressed_gzip()),
+            None => Router::new()
+                .fallback(assets::serve_embedded)
+                .layer(axum::middleware::from_fn(etag::conditional_get)),
+        };
+        let static_files = static_files
+            .layer(axum::middleware::from_fn_with_state(immutable, assets::serve_fingerprinted));
+
+        router
+            .nest(STATIC_PREFIX, static_files)
+            .layer(middlewares)
+    }
+}
+
+/// Sets the Content-Length of responses whose size is known, and removes it from statuses that
+/// never have a body, where axum sets it to 0; on a 304 it would claim the page is empty.
+fn content_length(mut response: Response<CompressionBody<Body>>) -> Response<CompressionBody<Body>> {
+    let status = response.status();
+    if status.is_informational() || status == StatusCode::NO_CONTENT || status == StatusCode::NOT_MODIFIED {
+        response.headers_mut().remove(CONTENT_LENGTH);
+        return response;
+    }
+
+    if let Some(length) = response.body().size_hint().exact() {
+        response.headers_mut().insert(CONTENT_LENGTH, HeaderValue::from(length));
+    }
+    response
+}
+
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate())
+        .expect("can install SIGTERM handler");
+
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::Read;
+    use axum::body::to_bytes;
+    use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, ETAG, IF_NONE_MATCH, RETRY_AFTER, VARY};
+    use http::Request;
+    use tower::ServiceExt;
+    use axum::routing::post;
+    use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+    use opentelemetry_proto::tonic::trace::v1::span::SpanKind;
+    use opentelemetry_proto::tonic::trace::v1::Span;
+    use prost::Message;
+    use tokio::sync::{mpsc, oneshot};
+    use crate::app::config::{Config, LogFileConfig, LogRotation, LoggingConfig, RouteRateLimit, TelemetryConfig};
+    use crate::app::config::Sources;
+    use crate::app::controller::{ControllerImpl, HX_REQUEST};
+    use crate::app::message::repository::instrumented::InstrumentedRepository;
+    use crate::app::message::repository::memory::InMemoryRepository;
+    use crate::app::message::{repository, Message as StoredMessage, PageToken};
+    use crate::app::message::repository::Repository;
+    use super::*;
+
+    const ENCODINGS: [&str; 4] = ["gzip", "br", "zstd", "deflate"];
+
+    fn app() -> Router {
+        app_with(InMemoryRepository::new())
+    }
+
+    fn app_with<R: Repository + 'static>(repository: R) -> Router {
+        let mut config = Config::default();
+        config.server.rate_limit.routes.clear();
+        app_with_config(config, repository)
+    }
+
+    fn app_with_config<R: Repository + 'static>(config: Config, repository: R) -> Router {
+        let reloader = Reloader::new(Sources::default(), config.clone());
+        let controller = ControllerImpl::new(repository, reloader.subscribe()).unwrap();
+        Server::new(config.server, controller, reloader).app(None)
+    }
+
+    async fn send(app: &Router, request: Request<Body>) -> (http::response::Parts, Vec<u8>) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = to_bytes(body, usize::MAX).await.unwrap();
+        (parts, body.to_vec())
+    }
+
+    fn get(uri: &str, encoding: &str) -> Request<Body> {
+        Request::get(uri).header(ACCEPT_ENCODING, encoding).body(Body::empty()).unwrap()
+    }
+
+    fn decode(encoding: &str, body: &[u8]) -> Vec<u8> {
+        let mut decoded = Vec::new();
+        match encoding {
+            "gzip" => flate2::read::GzDecoder::new(body).read_to_end(&mut decoded).unwrap(),
+            "deflate" => flate2::read::ZlibDecoder::new(body).read_to_end(&mut decoded).unwrap(),
+            "br" => brotli::Decompressor::new(body, 4096).read_to_end(&mut decoded).unwrap(),
+            "zstd" => zstd::stream::read::Decoder::new(body).unwrap().read_to_end(&mut decoded).unwrap(),
+            _ => panic!("unexpected encoding {}", encoding),
+        };
+        decoded
+    }
+
+    fn submit(email: &str) -> Request<Body> {
+        form(&format!("name=Someone&email={}&message=Hello", email))
+    }
+
+    fn submit_message(message: &str) -> Request<Body> {
+        form(&format!("name=Someone&email=someone@example.com&message={}", message))
+    }
+
+    fn form(body: &str) -> Request<Body> {
+        Request::post("/contact")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn header(parts: &http::response::Parts, name: http::HeaderName) -> Option<&str> {
+        parts.headers.get(name).map(|value| value.to_str().unwrap())
+    }
+
+    #[tokio::test]
+    async fn pre_compressed_pages_have_the_length_of_their_encoding() {
+        let app = app();
+        let (identity, html) = send(&app, get("/", "identity")).await;
+        assert_eq!(identity.status, StatusCode::OK);
+        assert_eq!(header(&identity, CONTENT_ENCODING), None);
+        assert_eq!(header(&identity, CONTENT_LENGTH), Some(html.len().to_string().as_str()));
+
+        for encoding in ENCODINGS {
+            let (parts, body) = send(&app, get("/", encoding)).await;
+
+            assert_eq!(parts.status, StatusCode::OK, "{}", encoding);
+            assert_eq!(header(&parts, CONTENT_ENCODING), Some(encoding));
+            assert_eq!(header(&parts, VARY), Some("accept-encoding"), "{}", encoding);
+            assert_eq!(header(&parts, CONTENT_LENGTH), Some(body.len().to_string().as_str()), "{}", encoding);
+            assert_eq!(decode(encoding, &body), html, "{}", encoding);
+        }
+    }
+
+    #[tokio::test]
+    async fn responses_compressed_on_the_fly_are_streamed_without_length() {
+        let app = app();
+        // Large enough for CompressionLayer, and neither pre-compressed nor cached.
+        let uri = "/messages?page_token=invalid";
+        let (identity, text) = send(&app, get(uri, "identity")).await;
+        assert_eq!(identity.status, StatusCode::BAD_REQUEST);
+        assert_eq!(header(&identity, CONTENT_LENGTH), Some(text.len().to_string().as_str()));
+
+        for encoding in ENCODINGS {
+            let (parts, body) = send(&app, get(uri, encoding)).await;
+
+            assert_eq!(parts.status, StatusCode::BAD_REQUEST, "{}", encoding);
+            assert_eq!(header(&parts, CONTENT_ENCODING), Some(encoding));
+            assert_eq!(header(&parts, VARY), Some("accept-encoding"), "{}", encoding);
+            assert_eq!(header(&parts, CONTENT_LENGTH), None, "{}", encoding);
+            assert_eq!(decode(encoding, &body), text, "{}", encoding);
+        }
+    }
+
+    #[tokio::test]
+    async fn not_modified_has_no_body_nor_length() {
+        let app = app();
+        for encoding in ENCODINGS {
+            let (parts, _) = send(&app, get("/about", encoding)).await;
+            let etag = header(&parts, ETAG).unwrap();
+
+            let request = Request::get("/about")
+                .header(ACCEPT_ENCODING, encoding)
+                .header(IF_NONE_MATCH, etag)
+                .body(Body::empty())
+                .unwrap();
+            let (parts, body) = send(&app, request).await;
+
+            assert_eq!(parts.status, StatusCode::NOT_MODIFIED, "{}", encoding);
+            assert_eq!(header(&parts, ETAG), Some(etag));
+            assert_eq!(header(&parts, CONTENT_LENGTH), None, "{}", encoding);
+            assert!(body.is_empty(), "{}", encoding);
+        }
+    }
+
+    #[tokio::test]
+    async fn no_content_has_no_body_nor_length() {
+        let app = app();
+        let report = r#"{"csp-report": {"document-uri": "https://example.com/", "violated-directive": "style-src", "blocked-uri": "inline"}}"#;
+
+        for encoding in ENCODINGS {
+            let request = Request::post(security::CSP_REPORT_PATH)
+                .header(ACCEPT_ENCODING, encoding)
+                .header(CONTENT_TYPE, "application/csp-report")
+                .body(Body::from(report))
+                .unwrap();
+            let (parts, body) = send(&app, request).await;
+
+            assert_eq!(parts.status, StatusCode::NO_CONTENT, "{}", encoding);
+            assert_eq!(header(&parts, CONTENT_ENCODING), None, "{}", encoding);
+            assert_eq!(header(&parts, CONTENT_LENGTH), None, "{}", encoding);
+            assert!(body.is_empty(), "{}", encoding);
+        }
+    }
+
+    /// Accepts OTLP/HTTP exports of spans, as a collector would, and passes the spans on.
+    async fn collector() -> (String, mpsc::UnboundedReceiver<Span>) {
+        let (spans, received) = mpsc::unbounded_channel();
+        let receiver = Router::new().route("/v1/traces", post(move |body: axum::body::Bytes| async move {
+            let request = ExportTraceServiceRequest::decode(body).unwrap();
+            let exported = request.resource_spans
+                .into_iter()
+                .flat_map(|resource| resource.scope_spans)
+                .flat_map(|scope| scope.spans);
+            for span in exported {
+                spans.send(span).unwrap();
+            }
+            StatusCode::OK
+        }));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+        (endpoint, received)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn exports_spans_around_storage_calls_and_rendering() {
+        let (endpoint, mut received) = collector().await;
+
+        // Installs the global subscriber, so no other test may call it. Logs go to a file to
+        // keep the test output readable.
+        let directory = std::env::temp_dir().join(format!("mywebsite-test-{}", std::process::id()));
+        let logging_config = LoggingConfig {
+            file: Some(LogFileConfig {
+                directory: directory.clone(),
+                prefix: "test.log".to_string(),
+                rotation: LogRotation::Never,
+                max_files: 1,
+            }),
+            ..LoggingConfig::default()
+        };
+        let telemetry = TelemetryConfig { otlp_endpoint: Some(endpoint), ..TelemetryConfig::default() };
+        let logging = logging::init(&logging_config, &telemetry).unwrap();
+
+        let app = app_with(InstrumentedRepository::new(InMemoryRepository::new()));
+        let contact = Request::post("/contact")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from("name=Alice&email=alice%40example.com&message=Hello+from+the+test"))
+            .unwrap();
+        assert_eq!(send(&app, contact).await.0.status, StatusCode::OK);
+
+        let messages = Request::get("/messages")
+            .header("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&app, messages).await.0.status, StatusCode::OK);
+
+        // Shutting the provider down exports what is left; it blocks until then.
+        tokio::task::spawn_blocking(move || drop(logging)).await.unwrap();
+        let _ = std::fs::remove_dir_all(directory);
+
+        let mut spans = Vec::new();
+        while let Ok(span) = received.try_recv() {
+            spans.push(span);
+        }
+        let span = |name: &str| spans.iter()
+            .find(|span| span.name == name)
+            .unwrap_or_else(|| panic!("no span named {} in {:?}", name, spans.iter().map(|s| &s.name).collect::<Vec<_>>()));
+
+        let post_contact = span("POST /contact");
+        assert_eq!(post_contact.kind, SpanKind::Server as i32);
+        assert!(post_contact.parent_span_id.is_empty());
+        let create = span("repository.create");
+        assert_eq!(create.trace_id, post_contact.trace_id);
+        assert_eq!(create.parent_span_id, post_contact.span_id);
+
+        // Continues the trace of the traceparent header.
+        let get_messages = span("GET /messages");
+        assert_eq!(get_messages.kind, SpanKind::Server as i32);
+        assert_eq!(get_messages.trace_id, hex("4bf92f3577b34da6a3ce929d0e0e4736"));
+        assert_eq!(get_messages.parent_span_id, hex("00f067aa0ba902b7"));
+        for name in ["repository.list", "render"] {
+            let child = span(name);
+            assert_eq!(child.trace_id, get_messages.trace_id, "{}", name);
+            assert_eq!(child.parent_span_id, get_messages.span_id, "{}", name);
+        }
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn rate_limited_submissions_are_told_when_to_retry() {
+        let mut config = Config::default();
+        let limit = RouteRateLimit { burst: 1, per_minute: 1, form_field: Some("email".to_string()) };
+        config.server.rate_limit.routes = BTreeMap::from([("POST /contact".to_string(), limit)]);
+        let app = app_with_config(config, InMemoryRepository::new());
+
+        let (accepted, _) = send(&app, submit("someone@example.com")).await;
+        assert_eq!(accepted.status, StatusCode::OK);
+
+        let (limited, body) = send(&app, submit("Someone@Example.com ")).await;
+        assert_eq!(limited.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header(&limited, RETRY_AFTER), Some("60"));
+        assert_eq!(header(&limited, CONTENT_TYPE), Some("text/html"));
+        assert_eq!(String::from_utf8(body).unwrap(), "<p>Too many requests, please try again in a minute.</p>");
+    }
+
+    #[tokio::test]
+    async fn htmx_requests_get_the_unavailable_message_without_the_page() {
+        let mut config = Config::default();
+        config.server.limits.max_in_flight_requests = 0;
+        let app = app_with_config(config, InMemoryRepository::new());
+
+        let (page, html) = send(&app, get("/", "identity")).await;
+        assert_eq!(page.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(String::from_utf8(html).unwrap().starts_with("<!DOCTYPE html>"));
+
+        let mut request = submit("someone@example.com");
+        request.headers_mut().insert(HX_REQUEST, HeaderValue::from_static("true"));
+        let (fragment, body) = send(&app, request).await;
+        assert_eq!(fragment.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(header(&fragment, RETRY_AFTER).is_some());
+        assert_eq!(String::from_utf8(body).unwrap(), "<p>The server is busy right now, please try again in a few seconds.</p>");
+    }
+
+    /// Signals that the messages were read, then waits to be released.
+    type Pause = (oneshot::Sender<()>, oneshot::Receiver<()>);
+
+    /// Reads the messages, then holds on to them until released on the first `list`, like a
+    /// render that is slower than a submission.
+    #[derive(Clone)]
+    struct SlowList {
+        messages: InMemoryRepository,
+        pause: Arc<std::sync::Mutex<Option<Pause>>>,
+    }
+
+    impl Repository for SlowList {
+        async fn create(&self, message: &StoredMessage) -> repository::Result<()> {
+            self.messages.create(message).await
+        }
+
+        async fn list(&self, max_results: usize, page_token: Option<PageToken>) -> repository::Result<(Vec<StoredMessage>, Option<PageToken>)> {
+            let listed = self.messages.list(max_results, page_token).await;
+            let pause = self.pause.lock().unwrap().take();
+            if let Some((read, release)) = pause {
+                read.send(()).unwrap();
+                release.await.unwrap();
+            }
+            listed
+        }
+
+        async fn check(&self) -> repository::Result<()> {
+            self.messages.check().await
+        }
+
+        async fn close(&self) -> repository::Result<()> {
+            self.messages.close().await
+        }
+    }
+
+    #[tokio::test]
+    async fn submissions_replace_the_cached_messages_page() {
+        let app = app();
+        let (_, before) = send(&app, get("/messages", "identity")).await;
+        assert!(!String::from_utf8(before).unwrap().contains("First message"));
+
+        let (sent, _) = send(&app, submit_message("First message")).await;
+        assert_eq!(sent.status, StatusCode::OK);
+
+        let (_, after) = send(&app, get("/messages", "identity")).await;
+        assert!(String::from_utf8(after).unwrap().contains("First message"));
+    }
+
+    #[tokio::test]
+    async fn pages_rendered_before_a_submission_are_not_cached() {
+        let (read, read_done) = oneshot::channel();
+        let (release, released) = oneshot::channel();
+        let repository = SlowList {
+            messages: InMemoryRepository::new(),
+            pause: Arc::new(std::sync::Mutex::new(Some((read, released)))),
+        };
+        let app = app_with(repository);
+
+        let render = tokio::spawn({
+            let app = app.clone();
+            async move { send(&app, get("/messages", "identity")).await }
+        });
+        read_done.await.unwrap();
+
+        let (sent, _) = send(&app, submit_message("Racing message")).await;
+        assert_eq!(sent.status, StatusCode::OK);
+        release.send(()).unwrap();
+
+        let (_, stale) = render.await.unwrap();
+        assert!(!String::from_utf8(stale).unwrap().contains("Racing message"));
+
+        let (_, fresh) = send(&app, get("/messages", "identity")).await;
+        assert!(String::from_utf8(fresh).unwrap().contains("Racing message"));
+    }
+
+    /// The nonce in the Content-Security-Policy of `parts`.
+    fn policy_nonce(parts: &http::response::Parts) -> String {
+        let policy = parts.headers.get(http::header::CONTENT_SECURITY_POLICY).unwrap().to_str().unwrap();
+        let start = policy.find("'nonce-").unwrap() + "'nonce-".len();
+        policy[start..].split('\'').next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn pages_get_the_nonce_of_their_request_and_keep_their_etag() {
+        use crate::app::config::SecurityConfig;
+        use crate::app::controller::encoding::{Effort, Encoded, Encoding};
+
+        let html = format!("<style nonce=\"{}\"></style>", security::nonce());
+        let page = Encoded::new(Encoding::Identity, html.as_bytes(), Effort::Fast);
+        let security = SecurityHeaders::new(&SecurityConfig::default(), false);
+        let app = Router::new()
+            .route("/", axum::routing::get(move || {
+                let page = page.clone();
+                async move { (page.headers(), Body::from(page.body())) }
+            }))
+            .layer(axum::middleware::from_fn(etag::conditional_get))
+            .layer(CompressionLayer::new())
+            .layer(axum::middleware::from_fn_with_state(security, security::security_headers));
+
+        let (first, body) = send(&app, get("/", "identity")).await;
+        let nonce = policy_nonce(&first);
+        assert_eq!(String::from_utf8(body).unwrap(), format!("<style nonce=\"{}\"></style>", nonce));
+
+        let (second, body) = send(&app, get("/", "gzip")).await;
+        assert_eq!(second.headers[CONTENT_ENCODING], "gzip");
+        let second_nonce = policy_nonce(&second);
+        assert_ne!(second_nonce, nonce);
+        assert_eq!(String::from_utf8(decode("gzip", &body)).unwrap(), format!("<style nonce=\"{}\"></style>", second_nonce));
+
+        let etag = first.headers[ETAG].clone();
+        assert!(etag.to_str().unwrap().starts_with("W/"));
+        assert_eq!(second.headers[ETAG], etag);
+
+        let revalidate = Request::get("/").header(IF_NONE_MATCH, etag).body(Body::empty()).unwrap();
+        let (not_modified, _) = send(&app, revalidate).await;
+        assert_eq!(not_modified.status, StatusCode::NOT_MODIFIED);
+        assert!(!not_modified.headers.contains_key(http::header::CONTENT_SECURITY_POLICY));
+    }
+}
@@ -0,0 +1,80 @@
+use std::sync::Arc;
+use std::time::Duration;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use http::{header, HeaderMap, HeaderValue, Method};
+use tokio::sync::Semaphore;
+use crate::app::config::{parse_route, LimitsConfig};
+use crate::app::controller::{Controller, HX_REQUEST};
+
+/// In-flight requests and timeouts of the public listeners.
+#[derive(Clone, Debug)]
+pub struct Overload<C> {
+    controller: C,
+    in_flight: Arc<Semaphore>,
+    timeout: Duration,
+    route_timeouts: Arc<Vec<(Method, String, Duration)>>,
+    retry_after: HeaderValue,
+}
+
+impl<C: Controller> Overload<C> {
+    /// Routes are checked when the configuration is loaded.
+    pub fn new(config: &LimitsConfig, controller: C) -> Self {
+        let route_timeouts = config.route_timeouts_ms
+            .iter()
+            .filter_map(|(route, timeout_ms)| {
+                let (method, path) = parse_route(route)?;
+                Some((method, path.to_string(), Duration::from_millis(*timeout_ms)))
+            })
+            .collect();
+
+        Overload {
+            controller,
+            in_flight: Arc::new(Semaphore::new(config.max_in_flight_requests)),
+            timeout: Duration::from_millis(config.request_timeout_ms),
+            route_timeouts: Arc::new(route_timeouts),
+            retry_after: HeaderValue::from(config.retry_after_secs),
+        }
+    }
+
+    fn timeout(&self, request: &Request) -> Duration {
+        self.route_timeouts
+            .iter()
+            .find(|(method, path, _)| method == request.method() && path == request.uri().path())
+            .map_or(self.timeout, |(_, _, timeout)| *timeout)
+    }
+
+    fn unavailable(&self, request_headers: &HeaderMap) -> Response {
+        let mut response = self.controller.unavailable(request_headers);
+        let headers = response.headers_mut();
+        headers.insert(header::RETRY_AFTER, self.retry_after.clone());
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        response
+    }
+}
+
+/// Answers with the 503 page when `max_in_flight_requests` are already being handled, and when
+/// a request takes longer than its timeout, dropping the work it was doing.
+///
+/// Shedding right away keeps latency bounded under overload: clients retry later instead of
+/// waiting behind a queue that only grows.
+pub async fn shed<C: Controller>(State(overload): State<Overload<C>>, request: Request, next: Next) -> Response {
+    let Ok(_permit) = overload.in_flight.clone().try_acquire_owned() else {
+        return overload.unavailable(request.headers());
+    };
+
+    // The request moves into the handler; the 503 page only needs to know how to encode, and
+    // whether HTMX asked for it.
+    let mut request_headers = HeaderMap::new();
+    for name in [header::ACCEPT_ENCODING, HX_REQUEST] {
+        if let Some(value) = request.headers().get(&name) {
+            request_headers.insert(name, value.clone());
+        }
+    }
+
+    match tokio::time::timeout(overload.timeout(&request), next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => overload.unavailable(&request_headers),
+    }
+}
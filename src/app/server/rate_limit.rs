@@ -0,0 +1,408 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use axum::body::{to_bytes, Body};
+use axum::extract::{ConnectInfo, Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use crate::app::config::{parse_route, Config, RateLimitConfig, RouteRateLimit};
+use crate::app::controller::CONTACT_PATH;
+use crate::app::metrics::{metrics, Submission};
+use crate::app::reload::Settings;
+
+/// Who a bucket is for. IPv6 clients are keyed by their /64, which usually belongs to a single
+/// subscriber who could otherwise rotate through addresses.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Key {
+    Address(Option<IpAddr>),
+    FormField(String),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets of one route.
+#[derive(Debug)]
+struct RouteLimiter {
+    method: Method,
+    path: String,
+    limit: RouteRateLimit,
+    burst: f64,
+    /// Tokens added per second.
+    rate: f64,
+    form_field: Option<String>,
+    buckets: Mutex<HashMap<Key, Bucket>>,
+}
+
+impl RouteLimiter {
+    fn new(method: Method, path: &str, limit: &RouteRateLimit) -> Self {
+        RouteLimiter {
+            method,
+            path: path.to_string(),
+            limit: limit.clone(),
+            burst: f64::from(limit.burst),
+            rate: f64::from(limit.per_minute) / 60.0,
+            form_field: limit.form_field.clone(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from the bucket of every key, or from none of them if one is empty, in
+    /// which case the time until it has a token again is returned.
+    fn acquire(&self, keys: &[Key], max_clients: usize, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+
+        let mut refilled = Vec::with_capacity(keys.len());
+        for key in keys {
+            let bucket = match buckets.get(key) {
+                Some(bucket) => self.refill(*bucket, now),
+                None => Bucket { tokens: self.burst, updated: now },
+            };
+            refilled.push(bucket);
+        }
+
+        let wait = refilled.iter()
+            .filter(|bucket| bucket.tokens < 1.0)
+            .map(|bucket| Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+            .max();
+        if let Some(wait) = wait {
+            return Err(wait);
+        }
+
+        for (key, mut bucket) in keys.iter().zip(refilled) {
+            bucket.tokens -= 1.0;
+            if !buckets.contains_key(key) && buckets.len() >= max_clients {
+                self.evict(&mut buckets, now);
+            }
+            buckets.insert(key.clone(), bucket);
+        }
+
+        Ok(())
+    }
+
+    fn refill(&self, bucket: Bucket, now: Instant) -> Bucket {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        Bucket {
+            tokens: (bucket.tokens + elapsed * self.rate).min(self.burst),
+            updated: now,
+        }
+    }
+
+    /// Drops the buckets that have refilled, which are the same as no bucket, or else the least
+    /// recently used one.
+    fn evict(&self, buckets: &mut HashMap<Key, Bucket>, now: Instant) {
+        buckets.retain(|_, bucket| self.refill(*bucket, now).tokens < self.burst);
+
+        if buckets.is_empty() {
+            return;
+        }
+
+        let oldest = buckets.iter()
+            .min_by_key(|(_, bucket)| bucket.updated)
+            .map(|(key, _)| key.clone());
+        if let Some(oldest) = oldest {
+            buckets.remove(&oldest);
+        }
+    }
+}
+
+/// Per-client limits on the routes listed in `server.rate_limit.routes`, following the
+/// configuration as it is reloaded.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    settings: Settings,
+    current: Arc<Mutex<Arc<Limits>>>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: Settings) -> Self {
+        let limits = Limits::new(settings.borrow().clone(), None);
+
+        RateLimiter {
+            settings,
+            current: Arc::new(Mutex::new(Arc::new(limits))),
+        }
+    }
+
+    /// The limits of the configuration in effect, rebuilt when it was reloaded.
+    fn limits(&self) -> Arc<Limits> {
+        let config = self.settings.borrow().clone();
+        let mut current = self.current.lock().unwrap();
+        if !Arc::ptr_eq(&current.config, &config) {
+            *current = Arc::new(Limits::new(config, Some(&current)));
+        }
+        current.clone()
+    }
+}
+
+/// The limits of one version of the configuration.
+#[derive(Debug)]
+struct Limits {
+    config: Arc<Config>,
+    routes: Vec<Arc<RouteLimiter>>,
+    max_clients: usize,
+    trust_forwarded_for: bool,
+}
+
+impl Limits {
+    /// Routes are checked when the configuration is loaded. Those whose limit did not change
+    /// keep their buckets from `previous`.
+    fn new(config: Arc<Config>, previous: Option<&Limits>) -> Self {
+        let RateLimitConfig { max_clients, trust_forwarded_for, routes } = &config.server.rate_limit;
+        let routes = routes
+            .iter()
+            .filter_map(|(route, limit)| {
+                let (method, path) = parse_route(route)?;
+                let kept = previous
+                    .into_iter()
+                    .flat_map(|previous| &previous.routes)
+                    .find(|kept| kept.method == method && kept.path == path && kept.limit == *limit);
+                Some(match kept {
+                    Some(kept) => kept.clone(),
+                    None => Arc::new(RouteLimiter::new(method, path, limit)),
+                })
+            })
+            .collect();
+
+        Limits {
+            routes,
+            max_clients: *max_clients,
+            trust_forwarded_for: *trust_forwarded_for,
+            config,
+        }
+    }
+
+    fn client(&self, request: &Request) -> Option<IpAddr> {
+        let forwarded = self.trust_forwarded_for
+            .then(|| forwarded_for(request.headers()))
+            .flatten();
+
+        let address = forwarded.or_else(|| {
+            let ConnectInfo(peer) = request.extensions().get::<ConnectInfo<Option<SocketAddr>>>()?;
+            peer.map(|peer| peer.ip())
+        })?;
+
+        match address {
+            IpAddr::V4(_) => Some(address),
+            IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+                Some(v4) => Some(IpAddr::V4(v4)),
+                None => Some(IpAddr::V6((u128::from(v6) & !0u128 << 64).into())),
+            },
+        }
+    }
+}
+
+/// Answers 429 Too Many Requests once a client has used up its requests on a limited route.
+pub async fn rate_limit(State(limiter): State<RateLimiter>, request: Request, next: Next) -> Response {
+    let limits = limiter.limits();
+    let route = limits.routes
+        .iter()
+        .find(|route| route.method == request.method() && route.path == request.uri().path());
+    let Some(route) = route else {
+        return next.run(request).await;
+    };
+
+    let mut keys = vec![Key::Address(limits.client(&request))];
+
+    // The field is read from the body, which then goes on to the handler untouched.
+    let request = match &route.form_field {
+        Some(field) if is_form(request.headers()) => {
+            let (parts, body) = request.into_parts();
+            let Ok(body) = to_bytes(body, usize::MAX).await else {
+                return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+            };
+
+            let value = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body)
+                .unwrap_or_default()
+                .into_iter()
+                .find(|(name, _)| name == field)
+                .map(|(_, value)| value.trim().to_lowercase());
+            keys.extend(value.filter(|value| !value.is_empty()).map(Key::FormField));
+
+            Request::from_parts(parts, Body::from(body))
+        }
+        _ => request,
+    };
+
+    match route.acquire(&keys, limits.max_clients, Instant::now()) {
+        Ok(()) => next.run(request).await,
+        Err(wait) => {
+            if route.method == Method::POST && route.path == CONTACT_PATH {
+                metrics().contact_submission(Submission::RateLimited);
+            }
+            too_many_requests(wait)
+        }
+    }
+}
+
+/// An HTML fragment, so that forms posting with HTMX can show it in place of the response.
+fn too_many_requests(wait: Duration) -> Response {
+    let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    let body = format!("<p>Too many requests, please try again in {}.</p>", human_duration(seconds));
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+    (StatusCode::TOO_MANY_REQUESTS, headers, body).into_response()
+}
+
+fn human_duration(seconds: u64) -> String {
+    match seconds {
+        1 => "a second".to_string(),
+        s if s < 60 => format!("{} seconds", s),
+        60 => "a minute".to_string(),
+        s => format!("{} minutes", s.div_ceil(60)),
+    }
+}
+
+/// The address the closest proxy saw the request coming from.
+fn forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    headers.get_all("x-forwarded-for")
+        .iter()
+        .next_back()?
+        .to_str()
+        .ok()?
+        .rsplit(',')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn is_form(headers: &HeaderMap) -> bool {
+    headers.get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use axum::body::Body;
+    use axum::extract::{ConnectInfo, Request};
+    use http::Method;
+    use crate::app::config::{Config, RouteRateLimit};
+    use super::{Key, Limits, RouteLimiter};
+
+    fn limiter(burst: u32, per_minute: u32) -> RouteLimiter {
+        let limit = RouteRateLimit { burst, per_minute, form_field: None };
+        RouteLimiter::new(Method::POST, "/contact", &limit)
+    }
+
+    fn address(address: &str) -> Key {
+        Key::Address(Some(address.parse().unwrap()))
+    }
+
+    fn client(limits: &Limits, peer: &str, forwarded_for: Option<&str>) -> Option<IpAddr> {
+        let mut request = Request::post("/contact");
+        if let Some(forwarded_for) = forwarded_for {
+            request = request.header("x-forwarded-for", forwarded_for);
+        }
+        let request = request
+            .extension(ConnectInfo(Some(peer.parse::<SocketAddr>().unwrap())))
+            .body(Body::empty())
+            .unwrap();
+        limits.client(&request)
+    }
+
+    #[test]
+    fn allows_a_burst_then_waits_for_a_token() {
+        let limiter = limiter(3, 60);
+        let keys = [address("192.0.2.1")];
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.acquire(&keys, 10, start), Ok(()));
+        }
+        assert_eq!(limiter.acquire(&keys, 10, start), Err(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn refills_at_the_configured_rate_up_to_the_burst() {
+        let limiter = limiter(2, 60);
+        let keys = [address("192.0.2.1")];
+        let start = Instant::now();
+        limiter.acquire(&keys, 10, start).unwrap();
+        limiter.acquire(&keys, 10, start).unwrap();
+
+        let half = start + Duration::from_millis(500);
+        assert_eq!(limiter.acquire(&keys, 10, half), Err(Duration::from_millis(500)));
+
+        let second = start + Duration::from_secs(1);
+        assert_eq!(limiter.acquire(&keys, 10, second), Ok(()));
+        assert!(limiter.acquire(&keys, 10, second).is_err());
+
+        // However long the client stays away, it only gets the burst back.
+        let later = second + Duration::from_secs(3600);
+        assert_eq!(limiter.acquire(&keys, 10, later), Ok(()));
+        assert_eq!(limiter.acquire(&keys, 10, later), Ok(()));
+        assert!(limiter.acquire(&keys, 10, later).is_err());
+    }
+
+    #[test]
+    fn a_shared_form_field_is_limited_whatever_the_address() {
+        let limiter = limiter(1, 1);
+        let email = Key::FormField("someone@example.com".to_string());
+        let start = Instant::now();
+
+        assert_eq!(limiter.acquire(&[address("192.0.2.1"), email.clone()], 10, start), Ok(()));
+        assert_eq!(limiter.acquire(&[address("192.0.2.2"), email.clone()], 10, start), Err(Duration::from_secs(60)));
+
+        // Nothing is taken when one of the keys is limited, so the address still has its token.
+        assert_eq!(limiter.acquire(&[address("192.0.2.2")], 10, start), Ok(()));
+    }
+
+    #[test]
+    fn keys_ipv6_clients_by_their_64_prefix() {
+        let limits = Limits::new(Arc::new(Config::default()), None);
+
+        let first = client(&limits, "[2001:db8:1:2:aaaa::1]:1000", None);
+        let same_prefix = client(&limits, "[2001:db8:1:2:bbbb::2]:2000", None);
+        let other_prefix = client(&limits, "[2001:db8:1:3::1]:1000", None);
+
+        assert_eq!(first, Some("2001:db8:1:2::".parse().unwrap()));
+        assert_eq!(first, same_prefix);
+        assert_ne!(first, other_prefix);
+        assert_eq!(client(&limits, "[::ffff:192.0.2.1]:1000", None), Some("192.0.2.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn uses_the_last_forwarded_address_only_when_trusted() {
+        let untrusted = Limits::new(Arc::new(Config::default()), None);
+        assert_eq!(client(&untrusted, "127.0.0.1:1000", Some("192.0.2.1")), Some("127.0.0.1".parse().unwrap()));
+
+        let mut config = Config::default();
+        config.server.rate_limit.trust_forwarded_for = true;
+        let trusted = Limits::new(Arc::new(config), None);
+        assert_eq!(client(&trusted, "127.0.0.1:1000", Some("198.51.100.1, 192.0.2.1")), Some("192.0.2.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn evicts_refilled_buckets_then_the_least_recently_used() {
+        let limiter = limiter(1, 60);
+        let start = Instant::now();
+        limiter.acquire(&[address("192.0.2.1")], 2, start).unwrap();
+        limiter.acquire(&[address("192.0.2.2")], 2, start + Duration::from_millis(100)).unwrap();
+
+        // Both are still refilling, so the least recently used one makes room.
+        limiter.acquire(&[address("192.0.2.3")], 2, start + Duration::from_millis(200)).unwrap();
+        let buckets = limiter.buckets.lock().unwrap().keys().cloned().collect::<Vec<_>>();
+        assert_eq!(buckets.len(), 2);
+        assert!(!buckets.contains(&address("192.0.2.1")));
+        drop(buckets);
+
+        // A second later both have refilled and are dropped together.
+        limiter.acquire(&[address("192.0.2.4")], 2, start + Duration::from_millis(1300)).unwrap();
+        let buckets = limiter.buckets.lock().unwrap().keys().cloned().collect::<Vec<_>>();
+        assert_eq!(buckets, [address("192.0.2.4")]);
+    }
+}
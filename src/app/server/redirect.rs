@@ -0,0 +1,39 @@
+use axum::extract::State;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Router;
+use http::{header, HeaderMap, StatusCode, Uri};
+
+/// Router answering every request with a 308 to the same path on the HTTPS listener.
+pub fn router(https_port: u16) -> Router {
+    Router::new()
+        .fallback(redirect_to_https)
+        .with_state(https_port)
+}
+
+async fn redirect_to_https(State(https_port): State<u16>, headers: HeaderMap, uri: Uri) -> Response {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .or_else(|| uri.host());
+
+    let Some(host) = host else {
+        return (StatusCode::BAD_REQUEST, "missing Host header").into_response();
+    };
+
+    let host = strip_port(host);
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let location = match https_port {
+        443 => format!("https://{}{}", host, path),
+        port => format!("https://{}:{}{}", host, port, path),
+    };
+
+    Redirect::permanent(&location).into_response()
+}
+
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        // IPv6 literals such as [::1] contain colons but end with a bracket.
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    }
+}
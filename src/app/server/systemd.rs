@@ -0,0 +1,86 @@
+use std::collections::VecDeque;
+use std::io;
+use std::os::fd::{BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::{Mutex, OnceLock};
+use socket2::{SockRef, Socket};
+use tokio::net::{TcpListener, UnixListener};
+use crate::app::server::listener::Listener;
+
+/// First file descriptor passed by systemd, see sd_listen_fds(3).
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Sockets passed by systemd that have not been claimed by a listener yet.
+static INHERITED: OnceLock<Mutex<VecDeque<(String, OwnedFd)>>> = OnceLock::new();
+
+/// Claims the socket systemd passed with `FileDescriptorName=name`, or the first unclaimed one.
+pub fn take_listener(name: Option<&str>) -> io::Result<Listener> {
+    let mut inherited = INHERITED
+        .get_or_init(|| Mutex::new(inherited_fds()))
+        .lock()
+        .unwrap();
+
+    let position = match name {
+        Some(name) => inherited.iter().position(|(n, _)| n == name),
+        None if inherited.is_empty() => None,
+        None => Some(0),
+    };
+
+    let Some((_, fd)) = position.and_then(|p| inherited.remove(p)) else {
+        let what = name.map(|n| format!("named {:?}", n)).unwrap_or_else(|| "at all".to_string());
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no socket passed by systemd {}", what)));
+    };
+
+    let socket = Socket::from(fd);
+    socket.set_nonblocking(true)?;
+    let address = socket.local_addr()?;
+
+    if address.as_socket().is_some() {
+        let listener = std::net::TcpListener::from(OwnedFd::from(socket));
+        TcpListener::from_std(listener).map(Listener::Tcp)
+    } else if address.is_unix() {
+        let listener = std::os::unix::net::UnixListener::from(OwnedFd::from(socket));
+        UnixListener::from_std(listener).map(|listener| Listener::Unix(listener, None))
+    } else {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "socket passed by systemd is neither TCP nor Unix"))
+    }
+}
+
+/// Reads `LISTEN_PID`, `LISTEN_FDS` and `LISTEN_FDNAMES`, ignoring them when they were meant
+/// for another process.
+fn inherited_fds() -> VecDeque<(String, OwnedFd)> {
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+
+    let count: RawFd = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0);
+
+    if !for_us || count <= 0 {
+        return VecDeque::new();
+    }
+
+    let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let mut names = names.split(':');
+
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count)
+        .filter_map(|fd| {
+            let name = names.next().unwrap_or_default().to_string();
+
+            // Descriptors that are not sockets are left alone rather than closed, they may have
+            // been reused by the process since it started.
+            // SAFETY: the descriptor is only borrowed for the duration of the check.
+            let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+            if SockRef::from(&borrowed).local_addr().is_err() {
+                tracing::warn!(fd, "ignoring file descriptor passed by systemd: not a socket");
+                return None;
+            }
+
+            // SAFETY: systemd passed this socket to this process and nothing else in it uses
+            // it; each one is moved out of the queue at most once.
+            Some((name, unsafe { OwnedFd::from_raw_fd(fd) }))
+        })
+        .collect()
+}
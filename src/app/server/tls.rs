@@ -0,0 +1,180 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use rustls::crypto::ring;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use tokio::sync::watch;
+use tokio_rustls::TlsAcceptor;
+use crate::app::config::TlsConfig;
+use crate::app::server::connection::stopping;
+
+/// Serves the certificate currently on disk, reloading it when the certificate or key file
+/// changes so renewals don't need a restart.
+#[derive(Debug)]
+pub struct ReloadingCertResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+    modified: Mutex<(SystemTime, SystemTime)>,
+}
+
+impl ReloadingCertResolver {
+    pub fn new(config: &TlsConfig) -> Result<Self, Error> {
+        let modified = modification_times(&config.cert_path, &config.key_path)?;
+        let key = load(&config.cert_path, &config.key_path)?;
+
+        Ok(ReloadingCertResolver {
+            cert_path: config.cert_path.clone(),
+            key_path: config.key_path.clone(),
+            current: RwLock::new(Arc::new(key)),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    /// Reloads the certificate if either file changed since the last load. An invalid new
+    /// certificate is reported and the previous one stays in use.
+    pub fn reload_if_changed(&self) {
+        let modified = match modification_times(&self.cert_path, &self.key_path) {
+            Ok(modified) => modified,
+            Err(e) => {
+                tracing::warn!(error = %e, "cannot check TLS certificate for changes");
+                return;
+            }
+        };
+
+        let mut last_modified = self.modified.lock().unwrap();
+        if *last_modified == modified {
+            return;
+        }
+        *last_modified = modified;
+
+        match load(&self.cert_path, &self.key_path) {
+            Ok(key) => {
+                *self.current.write().unwrap() = Arc::new(key);
+                tracing::info!(path = %self.cert_path.display(), "reloaded TLS certificate");
+            }
+            Err(e) =>
+                tracing::error!(error = %e, "cannot reload TLS certificate, keeping the previous one"),
+        }
+    }
+
+    /// Polls the certificate files every `interval` until `shutdown` flips to true.
+    pub async fn watch(self: Arc<Self>, interval: Duration, mut shutdown: watch::Receiver<bool>) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => self.reload_if_changed(),
+                _ = stopping(&mut shutdown) => return,
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// Builds an acceptor negotiating HTTP/2 or HTTP/1.1 through ALPN.
+pub fn acceptor(resolver: Arc<ReloadingCertResolver>) -> Result<TlsAcceptor, Error> {
+    let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(Error::InvalidConfiguration)?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn modification_times(cert_path: &Path, key_path: &Path) -> Result<(SystemTime, SystemTime), Error> {
+    let modified = |path: &Path| {
+        std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .map_err(|e| Error::CannotReadFile(path.to_path_buf(), e))
+    };
+
+    Ok((modified(cert_path)?, modified(key_path)?))
+}
+
+fn load(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, Error> {
+    let mut reader = open(cert_path)?;
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::CannotReadFile(cert_path.to_path_buf(), e))?;
+
+    if certs.is_empty() {
+        return Err(Error::NoCertificate(cert_path.to_path_buf()));
+    }
+
+    let mut reader = open(key_path)?;
+    let key = rustls_pemfile::private_key(&mut reader)
+        .map_err(|e| Error::CannotReadFile(key_path.to_path_buf(), e))?
+        .ok_or_else(|| Error::NoPrivateKey(key_path.to_path_buf()))?;
+
+    let key = ring::sign::any_supported_type(&key)
+        .map_err(|e| Error::UnsupportedPrivateKey(key_path.to_path_buf(), e))?;
+
+    // A certificate replaced without its key, or the other way round, would fail every handshake.
+    let certified = CertifiedKey::new(certs, key);
+    certified.keys_match()
+        .map_err(|e| Error::KeyMismatch(cert_path.to_path_buf(), key_path.to_path_buf(), e))?;
+
+    Ok(certified)
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, Error> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| Error::CannotReadFile(path.to_path_buf(), e))
+}
+
+#[derive(Debug)]
+pub enum Error {
+    CannotReadFile(PathBuf, std::io::Error),
+    NoCertificate(PathBuf),
+    NoPrivateKey(PathBuf),
+    UnsupportedPrivateKey(PathBuf, rustls::Error),
+    KeyMismatch(PathBuf, PathBuf, rustls::Error),
+    InvalidConfiguration(rustls::Error),
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::CannotReadFile(_, e) => Some(e),
+            Error::NoCertificate(_) => None,
+            Error::NoPrivateKey(_) => None,
+            Error::UnsupportedPrivateKey(_, e) => Some(e),
+            Error::KeyMismatch(_, _, e) => Some(e),
+            Error::InvalidConfiguration(e) => Some(e),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::CannotReadFile(path, e) =>
+                write!(f, "cannot read {}: {}", path.display(), e),
+            Error::NoCertificate(path) =>
+                write!(f, "no certificate found in {}", path.display()),
+            Error::NoPrivateKey(path) =>
+                write!(f, "no private key found in {}", path.display()),
+            Error::UnsupportedPrivateKey(path, e) =>
+                write!(f, "unsupported private key in {}: {}", path.display(), e),
+            Error::KeyMismatch(cert_path, key_path, e) =>
+                write!(f, "certificate in {} does not match the key in {}: {}", cert_path.display(), key_path.display(), e),
+            Error::InvalidConfiguration(e) =>
+                write!(f, "invalid TLS configuration: {}", e),
+        }
+    }
+}
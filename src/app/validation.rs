@@ -8,6 +8,18 @@ pub enum Error {
     InvalidPageToken,
 }
 
+impl Error {
+    /// Identifies the variant in metrics.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::TooShort => "too_short",
+            Error::TooLong => "too_long",
+            Error::InvalidEmail => "invalid_email",
+            Error::InvalidPageToken => "invalid_page_token",
+        }
+    }
+}
+
 impl std::error::Error for Error {}
 
 impl Display for Error {
@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+use crate::app::config::Sources;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Toy website server")]
+pub struct Cli {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start the web server (default).
+    Serve,
+    /// Apply pending schema migrations, creating the database if needed.
+    Migrate,
+    /// Write every stored message to a new JSON-lines file.
+    Export {
+        file: PathBuf,
+    },
+    /// Append the messages of a JSON-lines file to the message store.
+    Import {
+        file: PathBuf,
+    },
+    /// Validate the configuration, print it and run the startup checks, without binding any port.
+    Check,
+}
+
+#[derive(Debug, Args)]
+pub struct ConfigArgs {
+    /// Configuration file (defaults to mywebsite.toml when it exists).
+    #[arg(short, long, global = true, env = "MYWEBSITE_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Override any setting, e.g. `--set server.body_limit=4096`. Can be repeated.
+    #[arg(short = 's', long = "set", global = true, value_name = "KEY=VALUE")]
+    overrides: Vec<String>,
+
+    /// Shorthand for `--set server.listen_address=<ADDRESS>`. Can be repeated to listen on
+    /// several addresses.
+    #[arg(long, global = true, value_name = "ADDRESS")]
+    listen_address: Vec<String>,
+
+    /// Shorthand for `--set database.path=<PATH>`.
+    #[arg(long, global = true, value_name = "PATH")]
+    database: Option<PathBuf>,
+}
+
+impl ConfigArgs {
+    pub fn sources(&self) -> Sources {
+        let mut overrides = self.overrides.clone();
+
+        if !self.listen_address.is_empty() {
+            let addresses = self.listen_address
+                .iter()
+                .map(|address| toml::Value::String(address.clone()).to_string())
+                .collect::<Vec<_>>();
+            overrides.push(format!("server.listen_address=[{}]", addresses.join(", ")));
+        }
+
+        if let Some(database) = &self.database {
+            let path = toml::Value::String(database.display().to_string());
+            overrides.push(format!("database.path={}", path));
+        }
+
+        Sources {
+            file: self.config.clone(),
+            env: std::env::vars_os().collect(),
+            overrides,
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use crate::app::config::{Config, MigrationMode, StorageBackend};
+use crate::app::error::StartupError;
+use crate::app::message::repository::json::JSONRepository;
+use crate::app::message::repository::memory::InMemoryRepository;
+use crate::app::message::repository::sqlite::SQLiteRepository;
+use crate::app::migration::{Drift, Migrator};
+use crate::app::preflight::preflight;
+use crate::command::{connect, migration_error};
+
+/// Prints the effective configuration, then runs the same checks as `serve` without applying
+/// migrations or binding any port.
+pub async fn check(config: Config) -> Result<(), StartupError> {
+    let effective = toml::to_string_pretty(&config)
+        .expect("configuration is serializable");
+
+    println!("{}", effective);
+
+    match config.storage.backend {
+        StorageBackend::Sqlite => {
+            let pool = connect(&config.database).await?;
+
+            let drift = match config.database.migrations {
+                MigrationMode::Off => Vec::new(),
+                _ => Migrator::new(pool.clone()).drift().await.map_err(migration_error)?,
+            };
+
+            let (pending, drift): (Vec<_>, Vec<_>) = drift
+                .into_iter()
+                .partition(|d| matches!(d, Drift::Pending(_)));
+
+            let apply = matches!(config.database.migrations, MigrationMode::Apply);
+            if !drift.is_empty() || (!apply && !pending.is_empty()) {
+                return Err(StartupError::SchemaDrift(pending.into_iter().chain(drift).collect()));
+            }
+
+            if pending.is_empty() {
+                preflight(&config.server, &SQLiteRepository::new(pool.clone())).await?;
+            } else {
+                for migration in pending {
+                    println!("Pending migration, applied by serve: {}", migration);
+                }
+                // The message table may not exist yet; check everything but the storage.
+                preflight(&config.server, &InMemoryRepository::new()).await?;
+            }
+
+            pool.close().await;
+        }
+        StorageBackend::Json =>
+            preflight(&config.server, &JSONRepository::new(config.storage.json_path)).await?,
+        StorageBackend::Memory =>
+            preflight(&config.server, &InMemoryRepository::new()).await?,
+    }
+
+    println!("Configuration OK");
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use std::path::Path;
+use crate::app::config::{Config, StorageBackend};
+use crate::app::error::StartupError;
+use crate::app::message::repository;
+use crate::app::message::repository::json::JSONRepository;
+use crate::app::message::repository::Repository;
+use crate::app::message::repository::sqlite::SQLiteRepository;
+use crate::command::connect;
+
+pub async fn export(config: Config, file: &Path) -> Result<(), StartupError> {
+    let count = match config.storage.backend {
+        StorageBackend::Sqlite => {
+            let pool = connect(&config.database).await?;
+            let count = export_from(&SQLiteRepository::new(pool.clone()), file).await?;
+            pool.close().await;
+            count
+        }
+        StorageBackend::Json =>
+            export_from(&JSONRepository::new(config.storage.json_path), file).await?,
+        StorageBackend::Memory =>
+            return Err(StartupError::MemoryStorageNotSupported("export")),
+    };
+
+    println!("Exported {} messages to {}", count, file.display());
+    Ok(())
+}
+
+async fn export_from<R: Repository>(source: &R, file: &Path) -> Result<usize, StartupError> {
+    // Refuse to append to an existing file: an export must be a complete snapshot on its own.
+    tokio::fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(file)
+        .await
+        .map_err(|e| StartupError::CannotCreateExportFile(file.to_path_buf(), e))?;
+
+    repository::copy(source, &JSONRepository::new(file))
+        .await
+        .map_err(StartupError::CannotExportMessages)
+}
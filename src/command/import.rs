@@ -0,0 +1,48 @@
+use std::path::Path;
+use crate::app::config::{Config, StorageBackend};
+use crate::app::error::StartupError;
+use crate::app::message::repository;
+use crate::app::message::repository::json::JSONRepository;
+use crate::app::message::repository::sqlite::SQLiteRepository;
+use crate::command::connect;
+
+pub async fn import(config: Config, file: &Path) -> Result<(), StartupError> {
+    tokio::fs::File::open(file)
+        .await
+        .map_err(|e| StartupError::CannotOpenImportFile(file.to_path_buf(), e))?;
+
+    let source = JSONRepository::new(file);
+    let count = match config.storage.backend {
+        StorageBackend::Sqlite => {
+            let pool = connect(&config.database).await?;
+            let count = SQLiteRepository::new(pool.clone())
+                .import(&source)
+                .await
+                .map_err(StartupError::CannotImportMessages)?;
+            pool.close().await;
+            count
+        }
+        StorageBackend::Json => {
+            // Reading the file while appending to it would never reach its end.
+            if same_file(file, &config.storage.json_path) {
+                return Err(StartupError::CannotImportIntoItself(file.to_path_buf()));
+            }
+
+            repository::copy(&source, &JSONRepository::new(config.storage.json_path))
+                .await
+                .map_err(StartupError::CannotImportMessages)?
+        }
+        StorageBackend::Memory =>
+            return Err(StartupError::MemoryStorageNotSupported("import")),
+    };
+
+    println!("Imported {} messages from {}", count, file.display());
+    Ok(())
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
@@ -0,0 +1,27 @@
+use crate::app::config::Config;
+use crate::app::error::StartupError;
+use crate::app::migration::Migrator;
+use crate::command::migration_error;
+
+pub async fn migrate(config: Config) -> Result<(), StartupError> {
+    let pool = config.database.pool_options()
+        .connect_with(config.database.connect_options().create_if_missing(true))
+        .await
+        .map_err(StartupError::CannotCreateConnectionPool)?;
+
+    let applied = Migrator::new(pool.clone())
+        .migrate()
+        .await
+        .map_err(migration_error)?;
+
+    pool.close().await;
+
+    if applied.is_empty() {
+        println!("Database schema is up to date");
+    }
+    for migration in applied {
+        println!("Applied migration {}", migration.script());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,38 @@
+mod serve;
+mod migrate;
+mod export;
+mod import;
+mod check;
+
+use sqlx::SqlitePool;
+use crate::app::config::{Config, DatabaseConfig};
+use crate::app::error::StartupError;
+use crate::app::migration;
+use crate::cli::{Cli, Command};
+
+pub async fn run(cli: Cli) -> Result<(), StartupError> {
+    let sources = cli.config.sources();
+    let config = Config::load(&sources)?;
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve::serve(config, sources).await,
+        Command::Migrate => migrate::migrate(config).await,
+        Command::Export { file } => export::export(config, &file).await,
+        Command::Import { file } => import::import(config, &file).await,
+        Command::Check => check::check(config).await,
+    }
+}
+
+async fn connect(config: &DatabaseConfig) -> Result<SqlitePool, StartupError> {
+    config.pool_options()
+        .connect_with(config.connect_options())
+        .await
+        .map_err(StartupError::CannotCreateConnectionPool)
+}
+
+fn migration_error(e: migration::Error) -> StartupError {
+    match e {
+        migration::Error::SchemaDrift(drift) => StartupError::SchemaDrift(drift),
+        e => StartupError::CannotMigrateDatabase(e),
+    }
+}
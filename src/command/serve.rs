@@ -0,0 +1,119 @@
+use std::time::Duration;
+use sqlx::SqlitePool;
+use crate::app::assets::Manifest;
+use crate::app::config::{Config, DatabaseConfig, MigrationMode, Sources, StorageBackend};
+use crate::app::controller::ControllerImpl;
+use crate::app::error::StartupError;
+use crate::app::logging;
+use crate::app::logging::Levels;
+use crate::app::message::repository::instrumented::InstrumentedRepository;
+use crate::app::message::repository::json::JSONRepository;
+use crate::app::message::repository::memory::InMemoryRepository;
+use crate::app::message::repository::Repository;
+use crate::app::message::repository::sqlite::SQLiteRepository;
+use crate::app::metrics::metrics;
+use crate::app::migration::Migrator;
+use crate::app::preflight::preflight;
+use crate::app::reload::Reloader;
+use crate::app::server::{Server, Shutdown};
+use crate::command::{connect, migration_error};
+
+/// How long the message repository may take to close once the shutdown deadline has passed.
+const CLOSE_AFTER_DEADLINE: Duration = Duration::from_secs(1);
+
+pub async fn serve(config: Config, sources: Sources) -> Result<(), StartupError> {
+    let logging = logging::init(&config.logging, &config.telemetry)?;
+    let levels = logging.levels();
+
+    match config.storage.backend {
+        StorageBackend::Sqlite => {
+            let conn = prepare_database(&config.database).await?;
+            metrics().watch_pool(conn.clone());
+            run(config, sources, levels, SQLiteRepository::new(conn)).await
+        }
+        StorageBackend::Json => {
+            let repository = JSONRepository::new(&config.storage.json_path);
+            run(config, sources, levels, repository).await
+        }
+        StorageBackend::Memory =>
+            run(config, sources, levels, InMemoryRepository::new()).await,
+    }
+}
+
+/// Connects to the database and brings its schema in line with the embedded migrations, as
+/// configured by `database.migrations`.
+async fn prepare_database(config: &DatabaseConfig) -> Result<SqlitePool, StartupError> {
+    match config.migrations {
+        MigrationMode::Apply => {
+            let pool = config.pool_options()
+                .connect_with(config.connect_options().create_if_missing(true))
+                .await
+                .map_err(StartupError::CannotCreateConnectionPool)?;
+
+            let applied = Migrator::new(pool.clone())
+                .migrate()
+                .await
+                .map_err(migration_error)?;
+            for migration in applied {
+                tracing::info!(script = migration.script(), "applied migration");
+            }
+
+            Ok(pool)
+        }
+        MigrationMode::Verify => {
+            let pool = connect(config).await?;
+
+            let drift = Migrator::new(pool.clone())
+                .drift()
+                .await
+                .map_err(migration_error)?;
+            if !drift.is_empty() {
+                return Err(StartupError::SchemaDrift(drift));
+            }
+
+            Ok(pool)
+        }
+        MigrationMode::Off => connect(config).await,
+    }
+}
+
+async fn run<R: Repository + 'static>(config: Config, sources: Sources, levels: Levels, repository: R) -> Result<(), StartupError> {
+    preflight(&config.server, &repository).await?;
+
+    // Before pages are pre-rendered, so that they link fingerprinted assets. Files served from
+    // static_dir keep plain URLs: they may change under a fingerprint computed at startup.
+    if config.server.static_dir.is_none() {
+        Manifest::build().install();
+    }
+
+    let server_config = config.server.clone();
+    let reloader = Reloader::new(sources, config);
+    tokio::spawn(levels.follow(reloader.subscribe()));
+    let controller = ControllerImpl::new(InstrumentedRepository::new(repository.clone()), reloader.subscribe())
+        .map_err(StartupError::CannotRenderPages)?;
+    let server = Server::new(
+        server_config,
+        controller,
+        reloader,
+    );
+
+    match server.run().await? {
+        Shutdown::Graceful => {
+            repository.close()
+                .await
+                .map_err(StartupError::CouldNotCloseRepository)?;
+            tracing::info!("server finished");
+            Ok(())
+        }
+        Shutdown::DeadlineExceeded(deadline) => {
+            // The deadline is already spent: whatever the repository still has to flush gets a
+            // short grace period, then the process exits anyway.
+            match tokio::time::timeout(CLOSE_AFTER_DEADLINE, repository.close()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::error!(error = %e, "could not close message repository"),
+                Err(_) => tracing::warn!("message repository did not close in time"),
+            }
+            Err(StartupError::ShutdownDeadlineExceeded(deadline))
+        }
+    }
+}
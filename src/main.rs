@@ -1,33 +1,20 @@
-use sqlx::sqlite::SqlitePoolOptions;
-use crate::app::controller::ControllerImpl;
-use crate::app::error::StartupError;
-use crate::app::message::repository::sqlite::SQLiteRepository;
-use crate::app::server::Server;
+use std::process::ExitCode;
+use clap::Parser;
+use crate::cli::Cli;
 
 mod app;
+mod cli;
+mod command;
 
 #[tokio::main]
-async fn main() {
-    match run().await {
-        Ok(_) => println!("Server finished"),
-        Err(e) => eprintln!("error: {}", e),
-    }
-}
-
-async fn run() -> Result<(), StartupError> {
-    let conn = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect("db/database.sqlite")
-        .await
-        .map_err(|e| StartupError::CannotCreateConnectionPool(e))?;
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
 
-    let sqlite_repository = SQLiteRepository::new(conn.clone());
-
-    let controller = ControllerImpl::new(sqlite_repository);
-    let server = Server::new(
-        "127.0.0.1:3000".to_string(),
-        controller,
-    );
-
-    server.run().await
+    match command::run(cli).await {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
 }